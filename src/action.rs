@@ -0,0 +1,556 @@
+use crate::error::StalkerError;
+use crate::event::Change;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// The shell used by `stalk do --shell` when no shell is given.
+#[cfg(windows)]
+pub const DEFAULT_SHELL: &str = "cmd /C";
+#[cfg(not(windows))]
+pub const DEFAULT_SHELL: &str = "sh -c";
+
+/// Something run for every change. Commands from the actionlist are
+/// `ActionEntry`s; programs embedding stalker can add their own, such as an
+/// `FnAction` wrapping a closure.
+pub trait Action: Send {
+    /// How the action is shown in notices, e.g. its command line.
+    fn describe(&self) -> String;
+
+    /// The actionlist ID, for actions that come from the actionlist.
+    fn id(&self) -> Option<u32> {
+        None
+    }
+
+    /// Runs the action for `change`, with the variables from the instance's
+    /// env_file.
+    fn run(&mut self, change: &Change, env_file: &[(String, String)]) -> Result<ActionOutput, String>;
+
+    /// Like `run`, but tells `spawned` the pid of the process the action
+    /// starts, so a running stalker can list it. Actions that don't start
+    /// processes can leave this as is.
+    fn run_tracked(
+        &mut self,
+        change: &Change,
+        env_file: &[(String, String)],
+        spawned: &mut dyn FnMut(u32),
+    ) -> Result<ActionOutput, String> {
+        let _ = spawned;
+        self.run(change, env_file)
+    }
+}
+
+/// What an action that ran produced.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ActionOutput {
+    /// The output worth showing, e.g. what a command wrote to stdout.
+    pub output: String,
+    /// The exit code of a command; `None` for other actions, or a command
+    /// killed by a signal.
+    pub exit_code: Option<i32>,
+}
+
+/// An action implemented by a Rust closure.
+pub struct FnAction<F> {
+    name: String,
+    f: F,
+}
+
+impl<F> FnAction<F>
+where
+    F: FnMut(&Change) -> Result<String, String> + Send,
+{
+    pub fn new(name: impl Into<String>, f: F) -> FnAction<F> {
+        FnAction {
+            name: name.into(),
+            f,
+        }
+    }
+}
+
+impl<F> Action for FnAction<F>
+where
+    F: FnMut(&Change) -> Result<String, String> + Send,
+{
+    fn describe(&self) -> String {
+        self.name.clone()
+    }
+
+    fn run(&mut self, change: &Change, _env_file: &[(String, String)]) -> Result<ActionOutput, String> {
+        let output = (self.f)(change)?;
+        Ok(ActionOutput {
+            output,
+            exit_code: None,
+        })
+    }
+}
+
+/// A single line of the actionlist: a command followed by its options,
+/// stored the same way as stalklist entries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActionEntry {
+    /// Stable identifier shown by `stalk list-action`. Lines written before
+    /// IDs existed get one assigned when the actionlist is read.
+    pub id: Option<u32>,
+    pub command: String,
+    /// Run the command through this shell (e.g. `sh -c`, `bash -lc`)
+    /// instead of executing it directly.
+    pub shell: Option<String>,
+    /// Directory to run the command in; may contain placeholders such as `{dir}`.
+    pub cwd: Option<String>,
+    /// Extra variables set for the command; values may contain placeholders.
+    pub env: Vec<(String, String)>,
+    /// Start from an empty environment instead of inheriting stalker's.
+    pub clear_env: bool,
+}
+
+impl ActionEntry {
+    pub fn new(command: impl Into<String>) -> ActionEntry {
+        ActionEntry {
+            id: None,
+            command: command.into(),
+            shell: None,
+            cwd: None,
+            env: Vec::new(),
+            clear_env: false,
+        }
+    }
+
+    pub fn parse(line: &str) -> Result<ActionEntry, String> {
+        let mut fields = line.split('\t');
+        let command = fields.next().unwrap_or_default();
+        if command.is_empty() {
+            return Err(String::from("empty command"));
+        }
+
+        let mut action = ActionEntry::new(command);
+        for field in fields.filter(|f| !f.is_empty()) {
+            match field.split_once('=') {
+                Some(("shell", shell)) if !shell.is_empty() => {
+                    action.shell = Some(shell.to_string())
+                }
+                Some(("id", id)) => match id.parse() {
+                    Ok(id) => action.id = Some(id),
+                    Err(_) => return Err(format!("invalid id '{}' for {}", id, command)),
+                },
+                Some(("cwd", cwd)) if !cwd.is_empty() => action.cwd = Some(cwd.to_string()),
+                Some(("env", var)) => match var.split_once('=') {
+                    Some((key, value)) if !key.is_empty() => {
+                        action.env.push((key.to_string(), value.to_string()))
+                    }
+                    _ => return Err(format!("invalid env '{}' for {}", var, command)),
+                },
+                None if field == "clear_env" => action.clear_env = true,
+                _ => return Err(format!("unknown option '{}' for {}", field, command)),
+            }
+        }
+        Ok(action)
+    }
+
+    pub fn options(&self) -> Vec<String> {
+        let mut options = Vec::new();
+        if let Some(shell) = &self.shell {
+            options.push(format!("shell={}", shell));
+        }
+        if let Some(cwd) = &self.cwd {
+            options.push(format!("cwd={}", cwd));
+        }
+        for (key, value) in &self.env {
+            options.push(format!("env={}={}", key, value));
+        }
+        if self.clear_env {
+            options.push(String::from("clear_env"));
+        }
+        options
+    }
+
+    /// Checks that the action can be written as a line of the actionlist,
+    /// whose lines are split into fields at tabs.
+    pub fn check_line(&self) -> Result<(), String> {
+        let fields = [Some(&self.command), self.shell.as_ref(), self.cwd.as_ref()]
+            .into_iter()
+            .flatten()
+            .chain(self.env.iter().flat_map(|(key, value)| [key, value]));
+        for field in fields {
+            if field.contains(['\t', '\n', '\r']) {
+                return Err(format!(
+                    "'{}' contains a tab or a line break, which the actionlist can't store",
+                    field.escape_debug()
+                ));
+            }
+        }
+        match self.env.iter().find(|(key, _)| key.is_empty() || key.contains('=')) {
+            Some((key, _)) => Err(format!("invalid environment variable name '{}'", key)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn to_line(&self) -> String {
+        let mut fields = vec![self.command.clone()];
+        fields.extend(self.id.map(|id| format!("id={}", id)));
+        fields.extend(self.options());
+        fields.join("\t")
+    }
+
+    /// Builds the process to run for `change`, with the variables from the
+    /// instance's env_file set on top of the inherited environment.
+    ///
+    /// Without a shell the command is split into arguments with shell-style
+    /// quoting first, and placeholders are substituted inside each argument
+    /// afterwards, so a file name can never add or split arguments. With a
+    /// shell, placeholders become references to the `STALKER_*` variables,
+    /// so file names never end up in the script text.
+    pub fn build(&self, change: &Change, env_file: &[(String, String)]) -> Result<Command, String> {
+        let values = placeholder_values(change)?;
+        let argv = match &self.shell {
+            None => split(&self.command)?
+                .into_iter()
+                .map(|arg| substitute(&arg, &values))
+                .collect(),
+            Some(shell) => {
+                let mut argv = split(shell)?;
+                let cmd = argv.first().is_some_and(|program| {
+                    Path::new(program)
+                        .file_stem()
+                        .is_some_and(|stem| stem.eq_ignore_ascii_case("cmd"))
+                });
+                let values = PLACEHOLDERS.map(|(placeholder, var)| {
+                    let reference = if cmd {
+                        format!("\"%{}%\"", var)
+                    } else {
+                        format!("\"${}\"", var)
+                    };
+                    (placeholder, reference)
+                });
+                argv.push(substitute(&self.command, &values));
+                argv
+            }
+        };
+
+        let (program, args) = argv
+            .split_first()
+            .ok_or_else(|| format!("'{}' has no program to run", self.command))?;
+        let mut command = Command::new(program);
+        command.args(args);
+        if self.clear_env {
+            command.env_clear();
+        }
+        command.envs(env_file.iter().map(|(key, value)| (key, value)));
+        command.envs(change.env());
+        for (key, value) in &self.env {
+            command.env(key, substitute(value, &values));
+        }
+        if let Some(cwd) = &self.cwd {
+            command.current_dir(substitute(cwd, &values));
+        }
+        Ok(command)
+    }
+}
+
+/// Runs the command and waits for it, returning what it wrote to stdout.
+impl Action for ActionEntry {
+    fn describe(&self) -> String {
+        self.command.clone()
+    }
+
+    fn id(&self) -> Option<u32> {
+        self.id
+    }
+
+    fn run(&mut self, change: &Change, env_file: &[(String, String)]) -> Result<ActionOutput, String> {
+        self.run_tracked(change, env_file, &mut |_| {})
+    }
+
+    fn run_tracked(
+        &mut self,
+        change: &Change,
+        env_file: &[(String, String)],
+        spawned: &mut dyn FnMut(u32),
+    ) -> Result<ActionOutput, String> {
+        let output = self
+            .build(change, env_file)?
+            .stdout(Stdio::piped())
+            .spawn()
+            .and_then(|child| {
+                spawned(child.id());
+                child.wait_with_output()
+            })
+            .map_err(|e| e.to_string())?;
+        Ok(ActionOutput {
+            output: String::from_utf8_lossy(&output.stdout).into_owned(),
+            exit_code: output.status.code(),
+        })
+    }
+}
+
+/// Placeholders understood in actions, and the variable each one is exported as.
+const PLACEHOLDERS: [(&str, &str); 4] = [
+    ("{path}", "STALKER_PATH"),
+    ("{dir}", "STALKER_DIR"),
+    ("{event}", "STALKER_EVENT"),
+    ("{root}", "STALKER_ROOT"),
+];
+
+fn placeholder_values(change: &Change) -> Result<[(&'static str, String); 4], String> {
+    let utf8 = |path: &Path| {
+        path.to_str()
+            .map(String::from)
+            .ok_or_else(|| format!("{} is not valid UTF-8", path.display()))
+    };
+    Ok([
+        ("{path}", utf8(&change.path)?),
+        ("{dir}", utf8(&change.dir())?),
+        ("{event}", change.kind.to_string()),
+        ("{root}", utf8(&change.root)?),
+    ])
+}
+
+// Substitutes in a single pass, so a value that happens to contain another
+// placeholder is left alone.
+fn substitute(template: &str, values: &[(&str, String)]) -> String {
+    let mut result = String::new();
+    let mut rest = template;
+    while let Some((index, placeholder, value)) = values
+        .iter()
+        .filter_map(|(p, v)| rest.find(p).map(|i| (i, *p, v)))
+        .min_by_key(|(i, _, _)| *i)
+    {
+        result.push_str(&rest[..index]);
+        result.push_str(value);
+        rest = &rest[index + placeholder.len()..];
+    }
+    result.push_str(rest);
+    result
+}
+
+fn split(command: &str) -> Result<Vec<String>, String> {
+    shell_words::split(command).map_err(|e| format!("cannot parse '{}': {}", command, e))
+}
+
+impl fmt::Display for ActionEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let options = self.options();
+        if options.is_empty() {
+            write!(f, "{}", self.command)
+        } else {
+            write!(f, "{} ({})", self.command, options.join(", "))
+        }
+    }
+}
+
+/// Reads every action in the actionlist, giving IDs to those without one.
+pub fn read_actionlist(stalker_instance: &Path) -> Result<Vec<ActionEntry>, StalkerError> {
+    let path = stalker_instance.join("actionlist.txt");
+    let mut actions = fs::read_to_string(&path)
+        .map_err(|e| StalkerError::io(&path, e))?
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(number, line)| {
+            ActionEntry::parse(line).map_err(|e| StalkerError::parse(&path, number + 1, e))
+        })
+        .collect::<Result<Vec<ActionEntry>, StalkerError>>()?;
+
+    let first = next_id(&actions);
+    let unnumbered = actions.iter_mut().filter(|a| a.id.is_none());
+    for (id, action) in (first..).zip(unnumbered) {
+        action.id = Some(id);
+    }
+    Ok(actions)
+}
+
+pub fn write_actionlist(stalker_instance: &Path, actions: &[ActionEntry]) -> Result<(), StalkerError> {
+    for action in actions {
+        action.check_line().map_err(StalkerError::InvalidInput)?;
+    }
+    let path = stalker_instance.join("actionlist.txt");
+    let content: String = actions
+        .iter()
+        .map(|action| format!("{}\n", action.to_line()))
+        .collect();
+    fs::write(&path, content).map_err(|e| StalkerError::io(&path, e))
+}
+
+/// The ID a new action gets; IDs are never reused while higher ones exist.
+pub fn next_id(actions: &[ActionEntry]) -> u32 {
+    actions.iter().filter_map(|a| a.id).max().unwrap_or(0) + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::ChangeKind;
+    use std::ffi::OsStr;
+    use std::path::PathBuf;
+
+    fn change(path: &str) -> Change {
+        Change::new(PathBuf::from(path), ChangeKind::Write, PathBuf::from("/w"))
+    }
+
+    fn argv(command: &Command) -> Vec<&OsStr> {
+        std::iter::once(command.get_program())
+            .chain(command.get_args())
+            .collect()
+    }
+
+    #[test]
+    fn commands_are_split_like_a_shell_would() {
+        let action = ActionEntry::new("printf '%s\\n' \"a b\" c\\ d");
+        let command = action.build(&change("/w/a.txt"), &[]).unwrap();
+        assert_eq!(argv(&command), ["printf", "%s\\n", "a b", "c d"]);
+
+        let action = ActionEntry::new("echo 'open");
+        assert!(action.build(&change("/w/a.txt"), &[]).is_err());
+    }
+
+    #[test]
+    fn shell_commands_are_passed_to_the_shell_whole() {
+        let mut action = ActionEntry::new("make all && echo 'done'");
+        action.shell = Some(String::from("bash -lc"));
+        let command = action.build(&change("/w/a.txt"), &[]).unwrap();
+        assert_eq!(argv(&command), ["bash", "-lc", "make all && echo 'done'"]);
+
+        let line = action.to_line();
+        assert_eq!(line, "make all && echo 'done'\tshell=bash -lc");
+        assert_eq!(ActionEntry::parse(&line).unwrap(), action);
+        assert_eq!(ActionEntry::parse("echo").unwrap(), ActionEntry::new("echo"));
+    }
+
+    #[test]
+    fn placeholders_are_substituted_inside_single_arguments() {
+        let action = ActionEntry::new("cp '{path}' {dir}/backup");
+        let command = action.build(&change("/w/a b; rm -rf ~.txt"), &[]).unwrap();
+        assert_eq!(argv(&command), ["cp", "/w/a b; rm -rf ~.txt", "/w/backup"]);
+    }
+
+    #[test]
+    fn substituted_values_are_not_substituted_again() {
+        let action = ActionEntry::new("echo {path} {event}");
+        let command = action.build(&change("/w/{root}{event}"), &[]).unwrap();
+        assert_eq!(argv(&command), ["echo", "/w/{root}{event}", "write"]);
+    }
+
+    #[test]
+    fn shell_commands_reference_variables_instead_of_file_names() {
+        let mut action = ActionEntry::new("cat {path} | wc -l > {dir}/count");
+        action.shell = Some(String::from("sh -c"));
+        let command = action.build(&change("/w/$(reboot).txt"), &[]).unwrap();
+        assert_eq!(
+            argv(&command),
+            ["sh", "-c", "cat \"$STALKER_PATH\" | wc -l > \"$STALKER_DIR\"/count"]
+        );
+        assert_eq!(env(&command, "STALKER_PATH"), Some(OsStr::new("/w/$(reboot).txt")));
+        assert_eq!(env(&command, "STALKER_EVENT"), Some(OsStr::new("write")));
+        assert_eq!(env(&command, "STALKER_ROOT"), Some(OsStr::new("/w")));
+    }
+
+    #[test]
+    fn ids_round_trip_through_the_line() {
+        let action = ActionEntry {
+            id: Some(7),
+            ..ActionEntry::new("make")
+        };
+        assert_eq!(action.to_line(), "make\tid=7");
+        assert_eq!(ActionEntry::parse("make\tid=7").unwrap(), action);
+        assert_eq!(ActionEntry::parse("echo\tid=x").unwrap_err(), "invalid id 'x' for echo");
+        assert_eq!(next_id(&[action, ActionEntry::new("echo")]), 8);
+        assert_eq!(next_id(&[]), 1);
+    }
+
+    #[test]
+    fn actions_round_trip_through_the_actionlist() {
+        let dir = tempfile::tempdir().unwrap();
+        let actions = [
+            ActionEntry {
+                id: Some(1),
+                shell: Some(String::from("bash -lc")),
+                env: vec![(String::from("MSG"), String::from("a=b \\t 'quoted' \"{path}\""))],
+                ..ActionEntry::new("printf '%s\\n' \"$MSG\" | tr -d '\\r'")
+            },
+            ActionEntry {
+                id: Some(2),
+                cwd: Some(String::from("/w/with space")),
+                ..ActionEntry::new("echo C:\\dir\\{event}")
+            },
+        ];
+        write_actionlist(dir.path(), &actions).unwrap();
+        assert_eq!(read_actionlist(dir.path()).unwrap(), actions);
+    }
+
+    #[test]
+    fn tabs_and_line_breaks_are_not_written_to_the_actionlist() {
+        let dir = tempfile::tempdir().unwrap();
+        write_actionlist(dir.path(), &[ActionEntry::new("true")]).unwrap();
+        let unstorable = [
+            ActionEntry::new("echo a\tb"),
+            ActionEntry::new("echo a\necho b"),
+            ActionEntry {
+                shell: Some(String::from("sh\t-c")),
+                ..ActionEntry::new("true")
+            },
+            ActionEntry {
+                env: vec![(String::from("A"), String::from("1\r\n"))],
+                ..ActionEntry::new("true")
+            },
+            ActionEntry {
+                env: vec![(String::from("A=B"), String::from("1"))],
+                ..ActionEntry::new("true")
+            },
+        ];
+        for action in unstorable {
+            let written = write_actionlist(dir.path(), &[ActionEntry::new("true"), action]);
+            assert!(matches!(written, Err(StalkerError::InvalidInput(_))));
+        }
+        assert_eq!(fs::read_to_string(dir.path().join("actionlist.txt")).unwrap(), "true\n");
+    }
+
+    // The value `command` sets `key` to; later settings of a variable win.
+    fn env<'a>(command: &'a Command, key: &str) -> Option<&'a OsStr> {
+        command
+            .get_envs()
+            .filter(|(name, _)| *name == key)
+            .last()
+            .and_then(|(_, value)| value)
+    }
+
+    #[test]
+    fn cwd_and_env_take_placeholders() {
+        let mut action = ActionEntry::new("true");
+        action.cwd = Some(String::from("{dir}"));
+        action.env = vec![(String::from("TARGET"), String::from("{path}.out"))];
+        let command = action.build(&change("/w/a.txt"), &[]).unwrap();
+
+        assert_eq!(command.get_current_dir(), Some(Path::new("/w")));
+        assert_eq!(env(&command, "TARGET"), Some(OsStr::new("/w/a.txt.out")));
+    }
+
+    #[test]
+    fn cwd_and_env_round_trip_through_the_line() {
+        let action = ActionEntry {
+            cwd: Some(String::from("{root}")),
+            env: vec![(String::from("FLAGS"), String::from("a=b"))],
+            clear_env: true,
+            ..ActionEntry::new("make -C {dir}")
+        };
+        let line = action.to_line();
+        assert_eq!(line, "make -C {dir}\tcwd={root}\tenv=FLAGS=a=b\tclear_env");
+        assert_eq!(ActionEntry::parse(&line).unwrap(), action);
+        assert_eq!(ActionEntry::parse("echo\tenv==1").unwrap_err(), "invalid env '=1' for echo");
+        assert_eq!(ActionEntry::parse("echo\tloud").unwrap_err(), "unknown option 'loud' for echo");
+    }
+
+    #[test]
+    fn the_env_file_is_set_before_the_actions_own_variables() {
+        let mut action = ActionEntry::new("true");
+        action.env = vec![(String::from("TARGET"), String::from("from the action"))];
+        let env_file = [
+            (String::from("TARGET"), String::from("from env_file")),
+            (String::from("LEVEL"), String::from("debug")),
+        ];
+        let command = action.build(&change("/w/a.txt"), &env_file).unwrap();
+
+        assert_eq!(env(&command, "TARGET"), Some(OsStr::new("from the action")));
+        assert_eq!(env(&command, "LEVEL"), Some(OsStr::new("debug")));
+    }
+}
@@ -0,0 +1,133 @@
+use crate::control::PauseMode;
+use crate::error::StalkerError;
+use crate::paths;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const CONFIG_FILE: &str = "config.txt";
+
+/// Instance-wide settings stored as `key = value` lines in config.txt.
+/// Missing keys (or a missing file) fall back to the defaults.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstanceConfig {
+    /// How long after actions finish their own changes to watched files are ignored.
+    pub suppress_window: Duration,
+    /// How many triggers of the same file within `loop_window` count as a runaway loop.
+    pub loop_threshold: usize,
+    pub loop_window: Duration,
+    /// Ignore editor swap, lock and temp files (see `filter::EDITOR_PATTERNS`).
+    pub editor_filter: bool,
+    /// Extra file name globs to ignore on top of (or instead of) the editor filter.
+    pub ignore_patterns: Vec<String>,
+    /// A dotenv file whose variables are set for every action.
+    pub env_file: Option<PathBuf>,
+    /// What a pause without an explicit mode does with changes, e.g. one
+    /// asked for with SIGUSR1.
+    pub pause_mode: PauseMode,
+    /// Hold changes in a git repository while a checkout, rebase or merge
+    /// runs in it, and run the actions once afterwards.
+    pub git_pause: bool,
+}
+
+impl Default for InstanceConfig {
+    fn default() -> Self {
+        InstanceConfig {
+            suppress_window: Duration::from_secs(2),
+            loop_threshold: 5,
+            loop_window: Duration::from_secs(30),
+            editor_filter: true,
+            ignore_patterns: Vec::new(),
+            env_file: None,
+            pause_mode: PauseMode::Discard,
+            git_pause: true,
+        }
+    }
+}
+
+impl InstanceConfig {
+    pub fn load(stalker_instance: &Path) -> Result<InstanceConfig, StalkerError> {
+        let path = stalker_instance.join(CONFIG_FILE);
+        let mut config = InstanceConfig::default();
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(config),
+            Err(e) => return Err(StalkerError::io(&path, e)),
+        };
+
+        for (key, value) in parse_lines(&content) {
+            config.set(&key, &value).map_err(|message| StalkerError::Parse {
+                path: path.clone(),
+                message,
+            })?;
+        }
+        Ok(config)
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "suppress_window" => self.suppress_window = parse_seconds(key, value)?,
+            "loop_threshold" => {
+                self.loop_threshold = value
+                    .parse()
+                    .map_err(|_| format!("{} expects a whole number, got '{}'", key, value))?
+            }
+            "loop_window" => self.loop_window = parse_seconds(key, value)?,
+            "editor_filter" => self.editor_filter = parse_bool(key, value)?,
+            "ignore_patterns" => {
+                self.ignore_patterns = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|p| !p.is_empty())
+                    .map(String::from)
+                    .collect();
+                for pattern in &self.ignore_patterns {
+                    glob::Pattern::new(pattern).map_err(|e| {
+                        format!("{} has an invalid pattern '{}': {}", key, pattern, e)
+                    })?;
+                }
+            }
+            "env_file" if value.is_empty() => self.env_file = None,
+            "env_file" => {
+                let path = paths::normalize(Path::new(value))
+                    .map_err(|e| format!("{} is not a valid path: {}", key, e))?;
+                self.env_file = Some(path);
+            }
+            "pause_mode" => {
+                self.pause_mode = PauseMode::parse(value).ok_or_else(|| {
+                    format!("{} expects discard, accumulate or once, got '{}'", key, value)
+                })?
+            }
+            "git_pause" => self.git_pause = parse_bool(key, value)?,
+            _ => return Err(format!("unknown config key '{}'", key)),
+        }
+        Ok(())
+    }
+}
+
+/// Splits config.txt into `(key, value)` pairs, skipping blank lines and `#` comments.
+pub fn parse_lines(content: &str) -> Vec<(String, String)> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect()
+}
+
+fn parse_seconds(key: &str, value: &str) -> Result<Duration, String> {
+    value
+        .parse()
+        .map(Duration::from_secs)
+        .map_err(|_| format!("{} expects a number of seconds, got '{}'", key, value))
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool, String> {
+    match value {
+        "true" | "on" | "yes" => Ok(true),
+        "false" | "off" | "no" => Ok(false),
+        _ => Err(format!("{} expects true or false, got '{}'", key, value)),
+    }
+}
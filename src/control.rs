@@ -0,0 +1,502 @@
+use crate::error::StalkerError;
+use serde_json::json;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Instant;
+
+pub const SOCKET_FILE: &str = "stalker.sock";
+
+/// What a paused stalker does with the changes that come in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PauseMode {
+    /// Changes are dropped.
+    Discard,
+    /// Changes are held, and the actions run for each changed path on resume.
+    Accumulate,
+    /// Changes are held, and the actions run once on resume, for the last of
+    /// them with all of them in `Change::paths`.
+    Once,
+}
+
+impl PauseMode {
+    pub fn parse(value: &str) -> Option<PauseMode> {
+        match value {
+            "discard" => Some(PauseMode::Discard),
+            "accumulate" => Some(PauseMode::Accumulate),
+            "once" => Some(PauseMode::Once),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PauseMode::Discard => "discard",
+            PauseMode::Accumulate => "accumulate",
+            PauseMode::Once => "once",
+        }
+    }
+}
+
+/// An action that is running right now.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunningAction {
+    pub action: String,
+    pub id: Option<u32>,
+    pub path: PathBuf,
+    /// The process the action started, once it has started one.
+    pub pid: Option<u32>,
+    pub started: Instant,
+}
+
+/// Steers a [`Stalker`](crate::Stalker) from another thread, e.g. the
+/// control socket. Get one from `Stalker::controller` before running it.
+#[derive(Clone)]
+pub struct Controller {
+    shared: Arc<Shared>,
+    requests: Sender<Request>,
+}
+
+struct Shared {
+    /// How the stalker is paused, if it is.
+    paused: Mutex<Option<PauseMode>>,
+    /// The mode `pause` uses, from the config.
+    pause_mode: Mutex<PauseMode>,
+    shutdown: AtomicBool,
+    running: Mutex<Vec<RunningAction>>,
+}
+
+// What only the thread running the stalker can do, with where to send the result.
+pub(crate) enum Request {
+    Trigger(Option<PathBuf>, Sender<Result<usize, StalkerError>>),
+    Reload(Sender<Result<(), StalkerError>>),
+}
+
+impl Controller {
+    pub(crate) fn new() -> (Controller, Receiver<Request>) {
+        let (requests, receiver) = mpsc::channel();
+        let controller = Controller {
+            shared: Arc::new(Shared {
+                paused: Mutex::new(None),
+                pause_mode: Mutex::new(PauseMode::Discard),
+                shutdown: AtomicBool::new(false),
+                running: Mutex::new(Vec::new()),
+            }),
+            requests,
+        };
+        (controller, receiver)
+    }
+
+    /// Stops running actions for changes until `resume`, handling the
+    /// changes that come in meanwhile as the config's `pause_mode` says.
+    pub fn pause(&self) {
+        let mode = *lock(&self.shared.pause_mode);
+        self.pause_with(mode);
+    }
+
+    /// Like `pause`, with a mode of its own. Pausing again changes the mode.
+    pub fn pause_with(&self, mode: PauseMode) {
+        *lock(&self.shared.paused) = Some(mode);
+    }
+
+    pub fn resume(&self) {
+        *lock(&self.shared.paused) = None;
+    }
+
+    /// How the stalker is paused, or `None` while it runs actions.
+    pub fn paused(&self) -> Option<PauseMode> {
+        *lock(&self.shared.paused)
+    }
+
+    pub(crate) fn set_pause_mode(&self, mode: PauseMode) {
+        *lock(&self.shared.pause_mode) = mode;
+    }
+
+    /// Runs the actions now, as if `path` had changed, or every stalklist
+    /// path when `None`; even while paused. Returns how many paths they ran for.
+    pub fn trigger(&self, path: Option<PathBuf>) -> Result<usize, StalkerError> {
+        let (reply, result) = mpsc::channel();
+        self.send(Request::Trigger(path, reply))?;
+        result.recv().map_err(|_| stopped())?
+    }
+
+    /// Reads the instance's config.txt, stalklist and actionlist again and
+    /// applies them.
+    pub fn reload(&self) -> Result<(), StalkerError> {
+        let (reply, result) = mpsc::channel();
+        self.send(Request::Reload(reply))?;
+        result.recv().map_err(|_| stopped())?
+    }
+
+    /// Stops the stalker once the actions it is running have finished.
+    pub fn shutdown(&self) {
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.shared.shutdown.load(Ordering::SeqCst)
+    }
+
+    pub fn running_actions(&self) -> Vec<RunningAction> {
+        self.running().clone()
+    }
+
+    pub(crate) fn action_started(&self, action: RunningAction) {
+        self.running().push(action);
+    }
+
+    pub(crate) fn action_spawned(&self, pid: u32) {
+        if let Some(action) = self.running().last_mut() {
+            action.pid = Some(pid);
+        }
+    }
+
+    pub(crate) fn action_finished(&self) {
+        self.running().pop();
+    }
+
+    fn running(&self) -> MutexGuard<'_, Vec<RunningAction>> {
+        lock(&self.shared.running)
+    }
+
+    fn send(&self, request: Request) -> Result<(), StalkerError> {
+        self.requests.send(request).map_err(|_| stopped())
+    }
+}
+
+// The state stays usable even if an action panicked while it was locked.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn stopped() -> StalkerError {
+    StalkerError::Daemon(String::from("stalker has stopped"))
+}
+
+/// Pauses on SIGUSR1 and resumes on SIGUSR2, for as long as the process runs.
+#[cfg(unix)]
+pub fn handle_signals(controller: Controller) -> Result<(), StalkerError> {
+    use signal_hook::consts::{SIGUSR1, SIGUSR2};
+    let mut signals = signal_hook::iterator::Signals::new([SIGUSR1, SIGUSR2])
+        .map_err(|e| StalkerError::Daemon(format!("cannot handle signals: {}", e)))?;
+    std::thread::Builder::new()
+        .name(String::from("stalker-signals"))
+        .spawn(move || {
+            for signal in signals.forever() {
+                match signal {
+                    SIGUSR1 => controller.pause(),
+                    _ => controller.resume(),
+                }
+            }
+        })
+        .map_err(|e| StalkerError::Daemon(format!("cannot handle signals: {}", e)))?;
+    Ok(())
+}
+
+/// Answers a line of the control protocol, e.g. `pause` or
+/// `trigger /home/me/notes/todo.md`, with a JSON object: `{"ok":true,...}`,
+/// or `{"ok":false,"error":"...","kind":"..."}`, the kind being
+/// `invalid_input`, `not_found` or `failed`.
+pub fn respond(controller: &Controller, line: &str) -> serde_json::Value {
+    let (command, argument) = match line.trim().split_once(' ') {
+        Some((command, argument)) => (command, Some(argument.trim())),
+        None => (line.trim(), None),
+    };
+    let result = match command {
+        "pause" => match argument.map(|mode| (mode, PauseMode::parse(mode))) {
+            None => {
+                controller.pause();
+                Ok(json!({ "message": "paused" }))
+            }
+            Some((_, Some(mode))) => {
+                controller.pause_with(mode);
+                Ok(json!({ "message": format!("paused ({})", mode.as_str()) }))
+            }
+            Some((mode, None)) => Err(StalkerError::InvalidInput(format!(
+                "unknown pause mode '{}', expected discard, accumulate or once",
+                mode
+            ))),
+        },
+        "resume" => {
+            controller.resume();
+            Ok(json!({ "message": "resumed" }))
+        }
+        "trigger" => controller
+            .trigger(argument.map(PathBuf::from))
+            .map(|count| json!({ "message": format!("ran actions for {} path(s)", count), "triggered": count })),
+        "children" => {
+            let children: Vec<serde_json::Value> = controller
+                .running_actions()
+                .into_iter()
+                .map(|running| {
+                    json!({
+                        "action": running.action,
+                        "action_id": running.id,
+                        "path": running.path.to_string_lossy(),
+                        "pid": running.pid,
+                        "running_ms": running.started.elapsed().as_millis() as u64,
+                    })
+                })
+                .collect();
+            Ok(json!({ "children": children }))
+        }
+        "reload" => controller
+            .reload()
+            .map(|_| json!({ "message": "reloaded" })),
+        "shutdown" => {
+            controller.shutdown();
+            Ok(json!({ "message": "shutting down" }))
+        }
+        _ => Err(StalkerError::InvalidInput(format!(
+            "unknown command '{}', expected pause [MODE], resume, trigger [PATH], children, reload or shutdown",
+            command
+        ))),
+    };
+    match result {
+        Ok(mut response) => {
+            response["ok"] = json!(true);
+            response
+        }
+        Err(e) => json!({ "ok": false, "error": e.to_string(), "kind": error_kind(&e) }),
+    }
+}
+
+fn error_kind(error: &StalkerError) -> &'static str {
+    match error {
+        StalkerError::InvalidInput(_) => "invalid_input",
+        StalkerError::NotFound(_) => "not_found",
+        _ => "failed",
+    }
+}
+
+// The error a response with `"ok":false` stands for, of the kind `respond`
+// gave it where that can be told apart.
+fn response_error(response: &serde_json::Value) -> StalkerError {
+    let message = response["error"].as_str().unwrap_or("request failed").to_string();
+    match response["kind"].as_str() {
+        Some("invalid_input") => StalkerError::InvalidInput(message),
+        Some("not_found") => StalkerError::NotFound(message),
+        _ => StalkerError::Daemon(message),
+    }
+}
+
+#[cfg(unix)]
+pub use self::socket::{listen, send, ControlServer};
+
+#[cfg(unix)]
+mod socket {
+    use super::{respond, response_error, Controller, SOCKET_FILE};
+    use crate::error::StalkerError;
+    use std::fs;
+    use std::io::{self, BufRead, BufReader, Write};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread::{self, JoinHandle};
+    use std::time::Duration;
+
+    // How often the listener checks whether it was asked to stop.
+    const ACCEPT_POLL: Duration = Duration::from_millis(100);
+    // A client that connects but doesn't send a request is dropped after this.
+    const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// The control socket of a running stalker. The socket file is removed
+    /// when this is dropped.
+    pub struct ControlServer {
+        path: PathBuf,
+        stop: Arc<AtomicBool>,
+        thread: Option<JoinHandle<()>>,
+    }
+
+    impl Drop for ControlServer {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::SeqCst);
+            if let Some(thread) = self.thread.take() {
+                let _ = thread.join();
+            }
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    /// Listens on `stalker.sock` in the instance, answering each request on
+    /// its own thread.
+    pub fn listen(stalker_instance: &Path, controller: Controller) -> Result<ControlServer, StalkerError> {
+        let path = stalker_instance.join(SOCKET_FILE);
+        if path.exists() {
+            if UnixStream::connect(&path).is_ok() {
+                return Err(StalkerError::Daemon(format!(
+                    "another stalker is already listening on {}",
+                    path.display()
+                )));
+            }
+            // Left behind by a stalker that didn't exit cleanly.
+            fs::remove_file(&path).map_err(|e| StalkerError::io(&path, e))?;
+        }
+        let listener = UnixListener::bind(&path).map_err(|e| StalkerError::io(&path, e))?;
+        listener
+            .set_nonblocking(true)
+            .map_err(|e| StalkerError::io(&path, e))?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = Arc::clone(&stop);
+            thread::Builder::new()
+                .name(String::from("stalker-control"))
+                .spawn(move || accept(listener, controller, &stop))
+                .map_err(|e| StalkerError::io(&path, e))?
+        };
+        Ok(ControlServer {
+            path,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    fn accept(listener: UnixListener, controller: Controller, stop: &AtomicBool) {
+        while !stop.load(Ordering::SeqCst) {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    let controller = controller.clone();
+                    thread::spawn(move || {
+                        let _ = answer(stream, &controller);
+                    });
+                }
+                // Nothing to accept yet, or a client that gave up while connecting.
+                Err(_) => thread::sleep(ACCEPT_POLL),
+            }
+        }
+    }
+
+    fn answer(stream: UnixStream, controller: &Controller) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+        writeln!(&stream, "{}", respond(controller, &line))
+    }
+
+    /// Sends one request line to the stalker running for the instance and
+    /// returns its response, or the error it answered with. With no stalker
+    /// running, that is `StalkerError::NoInstance`.
+    pub fn send(stalker_instance: &Path, request: &str) -> Result<serde_json::Value, StalkerError> {
+        let path = stalker_instance.join(SOCKET_FILE);
+        let stream = UnixStream::connect(&path).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused => {
+                StalkerError::NoInstance(stalker_instance.to_path_buf())
+            }
+            _ => StalkerError::io(&path, e),
+        })?;
+        writeln!(&stream, "{}", request).map_err(|e| StalkerError::io(&path, e))?;
+        let mut line = String::new();
+        BufReader::new(&stream)
+            .read_line(&mut line)
+            .map_err(|e| StalkerError::io(&path, e))?;
+        let response: serde_json::Value = serde_json::from_str(&line).map_err(|e| {
+            StalkerError::Daemon(format!("unexpected response from stalker: {}", e))
+        })?;
+        if response["ok"] == true {
+            Ok(response)
+        } else {
+            Err(response_error(&response))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn pause_and_resume_requests_steer_the_controller() {
+        let (controller, _requests) = Controller::new();
+        controller.set_pause_mode(PauseMode::Accumulate);
+
+        assert_eq!(respond(&controller, "pause\n"), json!({ "ok": true, "message": "paused" }));
+        assert_eq!(controller.paused(), Some(PauseMode::Accumulate));
+        assert_eq!(
+            respond(&controller, "pause once"),
+            json!({ "ok": true, "message": "paused (once)" })
+        );
+        assert_eq!(controller.paused(), Some(PauseMode::Once));
+        assert_eq!(respond(&controller, "resume"), json!({ "ok": true, "message": "resumed" }));
+        assert_eq!(controller.paused(), None);
+    }
+
+    #[test]
+    fn children_lists_the_running_actions() {
+        let (controller, _requests) = Controller::new();
+        assert_eq!(respond(&controller, "children"), json!({ "ok": true, "children": [] }));
+
+        controller.action_started(RunningAction {
+            action: String::from("make"),
+            id: Some(2),
+            path: PathBuf::from("/w/a.c"),
+            pid: None,
+            started: Instant::now(),
+        });
+        controller.action_spawned(42);
+        let child = &respond(&controller, "children")["children"][0];
+        assert_eq!(child["action"], "make");
+        assert_eq!(child["action_id"], 2);
+        assert_eq!(child["path"], "/w/a.c");
+        assert_eq!(child["pid"], 42);
+    }
+
+    #[test]
+    fn shutdown_stops_the_stalker() {
+        let (controller, _requests) = Controller::new();
+        assert_eq!(respond(&controller, "shutdown"), json!({ "ok": true, "message": "shutting down" }));
+        assert!(controller.is_shutdown());
+    }
+
+    #[test]
+    fn bad_requests_are_invalid_input() {
+        let (controller, _requests) = Controller::new();
+        for request in ["frobnicate", "pause sometimes", ""] {
+            let response = respond(&controller, request);
+            assert_eq!(response["ok"], false);
+            assert_eq!(response["kind"], "invalid_input");
+            assert!(matches!(response_error(&response), StalkerError::InvalidInput(_)));
+        }
+        assert_eq!(controller.paused(), None);
+    }
+
+    #[test]
+    fn errors_of_the_running_stalker_keep_their_kind() {
+        let (controller, requests) = Controller::new();
+        let serving = thread::spawn(move || {
+            if let Ok(Request::Trigger(path, reply)) = requests.recv() {
+                let path = path.unwrap_or_default();
+                let _ = reply.send(Err(StalkerError::NotFound(format!("{} is not watched", path.display()))));
+            }
+        });
+        let response = respond(&controller, "trigger /w/elsewhere.txt");
+        assert_eq!(response["error"], "/w/elsewhere.txt is not watched");
+        assert!(matches!(response_error(&response), StalkerError::NotFound(_)));
+        serving.join().unwrap();
+
+        // The requests are no longer served.
+        let response = respond(&controller, "reload");
+        assert!(matches!(response_error(&response), StalkerError::Daemon(_)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn requests_go_through_the_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let (controller, _requests) = Controller::new();
+        let server = listen(dir.path(), controller.clone()).unwrap();
+
+        assert_eq!(send(dir.path(), "pause discard").unwrap()["message"], "paused (discard)");
+        assert_eq!(controller.paused(), Some(PauseMode::Discard));
+        assert!(matches!(send(dir.path(), "frobnicate"), Err(StalkerError::InvalidInput(_))));
+
+        drop(server);
+        thread::sleep(Duration::from_millis(10));
+        assert!(matches!(send(dir.path(), "resume"), Err(StalkerError::NoInstance(_))));
+    }
+}
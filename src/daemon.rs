@@ -0,0 +1,335 @@
+use crate::error::StalkerError;
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const PID_FILE: &str = "stalker.pid";
+pub const LOG_FILE: &str = "stalker.log";
+/// Held locked by the background stalker for as long as it runs.
+pub const LOCK_FILE: &str = "stalker.lock";
+
+/// How long `stop` waits for the daemon to finish its running actions.
+const STOP_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long `start` waits for the daemon to write its pidfile.
+const START_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Whether a background stalker is running for an instance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DaemonStatus {
+    Running(u32),
+    /// The pidfile names a process that is gone, e.g. after a crash, or
+    /// one that isn't a stalker.
+    Stale(u32),
+    Stopped,
+}
+
+pub fn log_file(stalker_instance: &Path) -> PathBuf {
+    stalker_instance.join(LOG_FILE)
+}
+
+pub fn status(stalker_instance: &Path) -> Result<DaemonStatus, StalkerError> {
+    let path = stalker_instance.join(PID_FILE);
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(DaemonStatus::Stopped),
+        Err(e) => return Err(StalkerError::io(&path, e)),
+    };
+    let pid = content
+        .trim()
+        .parse()
+        .map_err(|_| StalkerError::parse(&path, 1, format!("invalid pid '{}'", content.trim())))?;
+    // After a crash the pid may have been reused by an unrelated process.
+    Ok(if is_alive(pid) && is_stalker(pid) {
+        DaemonStatus::Running(pid)
+    } else {
+        DaemonStatus::Stale(pid)
+    })
+}
+
+/// Starts `stalk` again in the background with `args`, detached from the
+/// terminal and logging to the instance's log file. The new process has to
+/// `claim` the instance; this returns its pid once it has.
+pub fn start(stalker_instance: &Path, args: &[OsString]) -> Result<u32, StalkerError> {
+    if let DaemonStatus::Running(pid) = status(stalker_instance)? {
+        return Err(StalkerError::Daemon(format!(
+            "stalker is already running in the background (pid {})",
+            pid
+        )));
+    }
+
+    let log = log_file(stalker_instance);
+    let log_file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log)
+        .map_err(|e| StalkerError::io(&log, e))?;
+    let log_error = log_file.try_clone().map_err(|e| StalkerError::io(&log, e))?;
+    let exe = env::current_exe()
+        .map_err(|e| StalkerError::Daemon(format!("cannot find the stalk executable: {}", e)))?;
+
+    let mut command = Command::new(exe);
+    command
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(log_file)
+        .stderr(log_error);
+    detach(&mut command);
+    let mut child = command
+        .spawn()
+        .map_err(|e| StalkerError::Daemon(format!("cannot start stalker in the background: {}", e)))?;
+
+    let deadline = Instant::now() + START_TIMEOUT;
+    while status(stalker_instance)? != DaemonStatus::Running(child.id()) {
+        if let Ok(Some(exit)) = child.try_wait() {
+            return Err(StalkerError::Daemon(format!(
+                "stalker exited while starting ({}), see {}",
+                exit,
+                log.display()
+            )));
+        }
+        if Instant::now() >= deadline {
+            return Err(StalkerError::Daemon(format!(
+                "pid {} did not start within {}s, see {}",
+                child.id(),
+                START_TIMEOUT.as_secs(),
+                log.display()
+            )));
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    Ok(child.id())
+}
+
+/// The instance, claimed by the running process as its background stalker.
+/// Dropping it removes the pidfile.
+#[derive(Debug)]
+pub struct PidFile {
+    stalker_instance: PathBuf,
+    // Unlocked when closed, also when the process dies without removing the pidfile.
+    _lock: fs::File,
+}
+
+/// Claims the instance for the running process: locks the instance's lock
+/// file, so no second background stalker can run for it, and then writes
+/// the pidfile.
+pub fn claim(stalker_instance: &Path) -> Result<PidFile, StalkerError> {
+    let lock_path = stalker_instance.join(LOCK_FILE);
+    let lock = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .map_err(|e| StalkerError::io(&lock_path, e))?;
+    match lock.try_lock() {
+        Ok(()) => {}
+        Err(fs::TryLockError::WouldBlock) => {
+            return Err(StalkerError::Daemon(String::from(
+                "stalker is already running in the background",
+            )))
+        }
+        Err(fs::TryLockError::Error(e)) => return Err(StalkerError::io(&lock_path, e)),
+    }
+
+    // Written aside and renamed, so `status` never reads half a pid.
+    let path = stalker_instance.join(PID_FILE);
+    let written = stalker_instance.join(format!("{}.{}", PID_FILE, process::id()));
+    fs::write(&written, format!("{}\n", process::id()))
+        .and_then(|_| fs::rename(&written, &path))
+        .map_err(|e| {
+            let _ = fs::remove_file(&written);
+            StalkerError::io(&path, e)
+        })?;
+    Ok(PidFile {
+        stalker_instance: stalker_instance.to_path_buf(),
+        _lock: lock,
+    })
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        remove_pid_file(&self.stalker_instance, Some(process::id()));
+    }
+}
+
+/// Asks the background stalker to stop and waits until it has. Returns its pid.
+pub fn stop(stalker_instance: &Path) -> Result<u32, StalkerError> {
+    let pid = match status(stalker_instance)? {
+        DaemonStatus::Running(pid) => pid,
+        DaemonStatus::Stale(_) | DaemonStatus::Stopped => {
+            remove_pid_file(stalker_instance, None);
+            return Err(StalkerError::Daemon(String::from(
+                "stalker is not running in the background",
+            )));
+        }
+    };
+
+    terminate(pid).map_err(|e| StalkerError::Daemon(format!("cannot stop pid {}: {}", pid, e)))?;
+    let deadline = Instant::now() + STOP_TIMEOUT;
+    while is_alive(pid) {
+        if Instant::now() >= deadline {
+            return Err(StalkerError::Daemon(format!(
+                "pid {} did not stop within {}s",
+                pid,
+                STOP_TIMEOUT.as_secs()
+            )));
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    remove_pid_file(stalker_instance, None);
+    Ok(pid)
+}
+
+/// Removes the pidfile, but only if it names `pid` (when given), so a
+/// stalker that exits late can't remove the pidfile of its successor.
+pub fn remove_pid_file(stalker_instance: &Path, pid: Option<u32>) {
+    let path = stalker_instance.join(PID_FILE);
+    let ours = pid.is_none_or(|pid| {
+        fs::read_to_string(&path).is_ok_and(|content| content.trim() == pid.to_string())
+    });
+    if ours {
+        let _ = fs::remove_file(path);
+    }
+}
+
+/// A flag that is set when the process is asked to terminate (SIGTERM or
+/// Ctrl-C), so a running stalker can finish its actions before exiting.
+pub fn termination_flag() -> Result<Arc<AtomicBool>, StalkerError> {
+    let flag = Arc::new(AtomicBool::new(false));
+    #[cfg(unix)]
+    for signal in [signal_hook::consts::SIGTERM, signal_hook::consts::SIGINT] {
+        signal_hook::flag::register(signal, Arc::clone(&flag))
+            .map_err(|e| StalkerError::Daemon(format!("cannot handle signals: {}", e)))?;
+    }
+    Ok(flag)
+}
+
+#[cfg(unix)]
+fn detach(command: &mut Command) {
+    use std::os::unix::process::CommandExt;
+    // A new session has no controlling terminal, so closing the terminal
+    // doesn't take the stalker down with it.
+    unsafe {
+        command.pre_exec(|| {
+            libc::setsid();
+            Ok(())
+        });
+    }
+}
+
+#[cfg(windows)]
+fn detach(command: &mut Command) {
+    use std::os::windows::process::CommandExt;
+    const DETACHED_PROCESS: u32 = 0x0000_0008;
+    const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+    command.creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP);
+}
+
+#[cfg(unix)]
+fn is_alive(pid: u32) -> bool {
+    // Signal 0 only checks that the process exists and may be signalled.
+    unsafe { libc::kill(pid as libc::pid_t, 0) == 0 }
+}
+
+#[cfg(unix)]
+fn terminate(pid: u32) -> io::Result<()> {
+    if unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+// The name of the running executable, which a background stalker shares.
+#[cfg(not(target_os = "linux"))]
+fn exe_name() -> Option<OsString> {
+    env::current_exe().ok()?.file_name().map(OsString::from)
+}
+
+#[cfg(target_os = "linux")]
+fn is_stalker(pid: u32) -> bool {
+    let exe = match fs::read_link(format!("/proc/{}/exe", pid)) {
+        Ok(exe) => exe,
+        // Processes of other users can't be looked at, nor were they started by us.
+        Err(_) => return false,
+    };
+    // An executable replaced since the stalker started (e.g. by an update)
+    // shows up as deleted.
+    let exe = exe
+        .to_str()
+        .and_then(|exe| exe.strip_suffix(" (deleted)"))
+        .map_or(exe.clone(), PathBuf::from);
+    env::current_exe().is_ok_and(|current| current == exe)
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn is_stalker(pid: u32) -> bool {
+    Command::new("ps")
+        .args(["-p", &pid.to_string(), "-o", "comm="])
+        .output()
+        .is_ok_and(|output| {
+            let comm = String::from_utf8_lossy(&output.stdout);
+            Path::new(comm.trim()).file_name().map(OsString::from) == exe_name()
+        })
+}
+
+#[cfg(windows)]
+fn is_stalker(pid: u32) -> bool {
+    let name = match exe_name() {
+        Some(name) => name.to_string_lossy().to_lowercase(),
+        None => return false,
+    };
+    Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {}", pid), "/NH"])
+        .output()
+        .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).to_lowercase().contains(&name))
+}
+
+#[cfg(windows)]
+fn is_alive(pid: u32) -> bool {
+    Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {}", pid), "/NH"])
+        .output()
+        .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).contains(&pid.to_string()))
+}
+
+// Console processes without a window can't be asked to close on Windows.
+#[cfg(windows)]
+fn terminate(pid: u32) -> io::Result<()> {
+    let status = Command::new("taskkill")
+        .args(["/F", "/PID", &pid.to_string()])
+        .stdout(Stdio::null())
+        .status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::new(io::ErrorKind::Other, "taskkill failed"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn an_instance_is_claimed_by_one_stalker_at_a_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join(PID_FILE);
+
+        let claimed = claim(dir.path()).unwrap();
+        assert_eq!(fs::read_to_string(&pid_file).unwrap(), format!("{}\n", process::id()));
+        assert!(matches!(claim(dir.path()), Err(StalkerError::Daemon(_))));
+        // The failed claim leaves the pidfile of the running stalker alone.
+        assert!(pid_file.exists());
+
+        drop(claimed);
+        assert!(!pid_file.exists());
+        drop(claim(dir.path()).unwrap());
+    }
+}
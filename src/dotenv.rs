@@ -0,0 +1,172 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+/// Reads a dotenv file into `(key, value)` pairs, in file order.
+pub fn load(path: &Path) -> Result<Vec<(String, String)>, String> {
+    let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    parse(&content)
+}
+
+/// Parses dotenv syntax:
+///
+/// - `KEY=value` lines, optionally prefixed with `export `
+/// - blank lines and `#` comments, including ` # comments` after unquoted values
+/// - `'single quoted'` values, taken literally
+/// - `"double quoted"` values, with `\n`, `\t`, `\"`, `\\` and `\$` escapes
+/// - `${VAR}` and `$VAR` expansion in unquoted and double-quoted values, from
+///   earlier lines of the file first and then the environment (unset
+///   variables expand to nothing)
+pub fn parse(content: &str) -> Result<Vec<(String, String)>, String> {
+    let mut vars: Vec<(String, String)> = Vec::new();
+
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let error = |message: &str| format!("line {}: {}", number + 1, message);
+
+        let (key, raw) = line
+            .split_once('=')
+            .ok_or_else(|| error("expected KEY=VALUE"))?;
+        let key = key.trim();
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(error(&format!("invalid variable name '{}'", key)));
+        }
+
+        let raw = raw.trim_start();
+        let value = if let Some(quoted) = raw.strip_prefix('\'') {
+            let end = quoted
+                .find('\'')
+                .ok_or_else(|| error("unclosed single quote"))?;
+            quoted[..end].to_string()
+        } else if let Some(quoted) = raw.strip_prefix('"') {
+            let unescaped = unescape(quoted).ok_or_else(|| error("unclosed double quote"))?;
+            expand(&unescaped, &vars)
+        } else {
+            let value = match raw.find(" #") {
+                Some(comment) => &raw[..comment],
+                None => raw,
+            };
+            expand(value.trim_end(), &vars)
+        };
+
+        vars.retain(|(k, _)| k != key);
+        vars.push((key.to_string(), value));
+    }
+    Ok(vars)
+}
+
+// Stands in for an escaped `\$` until expansion is done.
+const ESCAPED_DOLLAR: char = '\u{E000}';
+
+// Unescapes a double-quoted value up to its closing quote.
+fn unescape(quoted: &str) -> Option<String> {
+    let mut value = String::new();
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Some(value),
+            '\\' => match chars.next()? {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                '$' => value.push(ESCAPED_DOLLAR),
+                other => value.push(other),
+            },
+            other => value.push(other),
+        }
+    }
+    None
+}
+
+fn expand(value: &str, vars: &[(String, String)]) -> String {
+    let lookup = |name: &str| {
+        vars.iter()
+            .rev()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.clone())
+            .or_else(|| env::var(name).ok())
+            .unwrap_or_default()
+    };
+
+    let mut expanded = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let (name, remainder) = match after.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) => (&braced[..end], &braced[end + 1..]),
+                None => ("", after),
+            },
+            None => {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                (&after[..end], &after[end..])
+            }
+        };
+        if name.is_empty() {
+            expanded.push('$');
+        } else {
+            expanded.push_str(&lookup(name));
+        }
+        rest = remainder;
+    }
+    expanded.push_str(rest);
+    expanded.replace(ESCAPED_DOLLAR, "$")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn plain_values_comments_and_export() {
+        let content = "\n# a comment\nA=1\nexport B = two words # trailing\nC=x#not-a-comment\n";
+        assert_eq!(
+            parse(content).unwrap(),
+            pairs(&[("A", "1"), ("B", "two words"), ("C", "x#not-a-comment")])
+        );
+    }
+
+    #[test]
+    fn single_quotes_are_literal() {
+        assert_eq!(
+            parse("A=1\nB='$A \\n # ${A}'").unwrap(),
+            pairs(&[("A", "1"), ("B", "$A \\n # ${A}")])
+        );
+    }
+
+    #[test]
+    fn double_quotes_unescape_and_expand() {
+        assert_eq!(
+            parse("A=1\nB=\"a\\tb\\n\\\"$A\\\" ${A}x \\$A \\\\\" # comment").unwrap(),
+            pairs(&[("A", "1"), ("B", "a\tb\n\"1\" 1x $A \\")])
+        );
+    }
+
+    #[test]
+    fn expansion_uses_earlier_lines_and_unset_variables_are_empty() {
+        assert_eq!(
+            parse("A=1\nB=$A-${STALKER_TEST_UNSET_VARIABLE}-$\nA=2\nC=$A").unwrap(),
+            pairs(&[("B", "1--$"), ("A", "2"), ("C", "2")])
+        );
+    }
+
+    #[test]
+    fn malformed_lines_are_errors_with_their_line_number() {
+        assert_eq!(parse("A=1\nno equals sign").unwrap_err(), "line 2: expected KEY=VALUE");
+        assert_eq!(parse("BAD-KEY=1").unwrap_err(), "line 1: invalid variable name 'BAD-KEY'");
+        assert_eq!(parse("A='open").unwrap_err(), "line 1: unclosed single quote");
+        assert_eq!(parse("A=\"open").unwrap_err(), "line 1: unclosed double quote");
+    }
+}
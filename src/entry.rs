@@ -0,0 +1,177 @@
+use crate::error::StalkerError;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Which path an event under a followed symlink is reported against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymlinkReport {
+    /// The path as seen through the link (e.g. `proj/vendor/lib.rs`).
+    Link,
+    /// The resolved path the link points to (e.g. `/opt/vendor/lib.rs`).
+    Target,
+}
+
+/// A single line of the stalklist: a path followed by its options.
+///
+/// Options are stored after the path as tab-separated `key` or `key=value`
+/// fields, so a plain path on its own line is still a valid entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchEntry {
+    pub path: PathBuf,
+    pub follow_symlinks: bool,
+    pub report: SymlinkReport,
+    /// How many levels below the path are watched; 1 means only its direct children.
+    pub max_depth: Option<usize>,
+    /// Skip files and directories whose name starts with a dot.
+    pub no_hidden: bool,
+}
+
+impl WatchEntry {
+    pub fn new(path: impl Into<PathBuf>) -> WatchEntry {
+        WatchEntry {
+            path: path.into(),
+            follow_symlinks: false,
+            report: SymlinkReport::Link,
+            max_depth: None,
+            no_hidden: false,
+        }
+    }
+
+    pub fn parse(line: &str) -> Result<WatchEntry, String> {
+        let mut fields = line.split('\t');
+        let path = fields.next().unwrap_or_default();
+        if path.is_empty() {
+            return Err(String::from("empty path"));
+        }
+
+        let mut entry = WatchEntry::new(path);
+        for field in fields.filter(|f| !f.is_empty()) {
+            let (key, value) = match field.split_once('=') {
+                Some((key, value)) => (key, Some(value)),
+                None => (field, None),
+            };
+            match (key, value) {
+                ("follow_symlinks", None) => entry.follow_symlinks = true,
+                ("report", Some("link")) => entry.report = SymlinkReport::Link,
+                ("report", Some("target")) => entry.report = SymlinkReport::Target,
+                ("max_depth", Some(depth)) => match depth.parse() {
+                    Ok(depth) => entry.max_depth = Some(depth),
+                    Err(_) => return Err(format!("invalid max_depth '{}' for {}", depth, path)),
+                },
+                ("no_hidden", None) => entry.no_hidden = true,
+                _ => return Err(format!("unknown option '{}' for {}", field, path)),
+            }
+        }
+        Ok(entry)
+    }
+
+    pub fn options(&self) -> Vec<String> {
+        let mut options = Vec::new();
+        if self.follow_symlinks {
+            options.push(String::from("follow_symlinks"));
+        }
+        if self.report == SymlinkReport::Target {
+            options.push(String::from("report=target"));
+        }
+        if let Some(depth) = self.max_depth {
+            options.push(format!("max_depth={}", depth));
+        }
+        if self.no_hidden {
+            options.push(String::from("no_hidden"));
+        }
+        options
+    }
+
+    /// Whether the watcher has to be told about each directory separately
+    /// instead of watching the whole tree recursively.
+    pub fn watches_per_directory(&self) -> bool {
+        self.max_depth.is_some() || self.no_hidden
+    }
+
+    pub fn to_line(&self) -> String {
+        let mut fields = vec![self.path.display().to_string()];
+        fields.extend(self.options());
+        fields.join("\t")
+    }
+}
+
+impl fmt::Display for WatchEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let options = self.options();
+        if options.is_empty() {
+            write!(f, "{}", self.path.display())
+        } else {
+            write!(f, "{} ({})", self.path.display(), options.join(", "))
+        }
+    }
+}
+
+pub fn read_stalklist(stalker_instance: &Path) -> Result<Vec<WatchEntry>, StalkerError> {
+    let path = stalker_instance.join("stalklist.txt");
+    fs::read_to_string(&path)
+        .map_err(|e| StalkerError::io(&path, e))?
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(number, line)| {
+            WatchEntry::parse(line).map_err(|e| StalkerError::parse(&path, number + 1, e))
+        })
+        .collect()
+}
+
+pub fn write_stalklist(stalker_instance: &Path, entries: &[WatchEntry]) -> Result<(), StalkerError> {
+    let path = stalker_instance.join("stalklist.txt");
+    let content: String = entries
+        .iter()
+        .map(|entry| format!("{}\n", entry.to_line()))
+        .collect();
+    fs::write(&path, content).map_err(|e| StalkerError::io(&path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_plain_path_is_an_entry_without_options() {
+        assert_eq!(WatchEntry::parse("/w/a b.txt").unwrap(), WatchEntry::new("/w/a b.txt"));
+    }
+
+    #[test]
+    fn symlink_options_round_trip_through_the_line() {
+        let entry = WatchEntry {
+            follow_symlinks: true,
+            report: SymlinkReport::Target,
+            ..WatchEntry::new("/w/project")
+        };
+        let line = entry.to_line();
+        assert_eq!(line, "/w/project\tfollow_symlinks\treport=target");
+        assert_eq!(WatchEntry::parse(&line).unwrap(), entry);
+    }
+
+    #[test]
+    fn depth_and_hidden_options_round_trip_through_the_line() {
+        let entry = WatchEntry {
+            max_depth: Some(2),
+            no_hidden: true,
+            ..WatchEntry::new("/w/project")
+        };
+        let line = entry.to_line();
+        assert_eq!(line, "/w/project\tmax_depth=2\tno_hidden");
+        assert_eq!(WatchEntry::parse(&line).unwrap(), entry);
+        assert_eq!(
+            WatchEntry::parse("/w\tmax_depth=deep").unwrap_err(),
+            "invalid max_depth 'deep' for /w"
+        );
+    }
+
+    #[test]
+    fn unknown_options_are_errors() {
+        assert_eq!(
+            WatchEntry::parse("/w\tcolour").unwrap_err(),
+            "unknown option 'colour' for /w"
+        );
+        assert_eq!(WatchEntry::parse("\tfollow_symlinks").unwrap_err(), "empty path");
+    }
+}
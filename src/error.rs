@@ -0,0 +1,83 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Everything a stalker command can fail with.
+#[derive(Debug)]
+pub enum StalkerError {
+    /// There is no stalker instance at this path; `stalk init` creates one.
+    NoInstance(PathBuf),
+    /// Reading or writing a file failed, or another operating system call
+    /// that no file is involved in (with an empty path).
+    Io { path: PathBuf, source: io::Error },
+    /// The stalklist, actionlist or config of the instance has a line that can't be parsed.
+    Parse { path: PathBuf, message: String },
+    /// An argument was rejected, e.g. a path that can't be normalized or an
+    /// unknown control request.
+    InvalidInput(String),
+    /// Nothing in the stalklist or actionlist matched.
+    NotFound(String),
+    /// The editor used by `stalk edit-action` failed.
+    Editor(String),
+    /// Watching the stalklist paths failed.
+    Watch(notify::Error),
+    /// Starting, stopping or controlling a running stalker failed.
+    Daemon(String),
+}
+
+impl StalkerError {
+    pub fn io(path: &Path, source: io::Error) -> StalkerError {
+        StalkerError::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    /// A parse error on the 1-based `line` of `path`.
+    pub fn parse(path: &Path, line: usize, message: impl fmt::Display) -> StalkerError {
+        StalkerError::Parse {
+            path: path.to_path_buf(),
+            message: format!("line {}: {}", line, message),
+        }
+    }
+}
+
+impl fmt::Display for StalkerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StalkerError::NoInstance(path) => {
+                write!(f, "no stalker instance found at {}", path.display())
+            }
+            StalkerError::Io { path, source } if path.as_os_str().is_empty() => {
+                write!(f, "{}", source)
+            }
+            StalkerError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            StalkerError::Parse { path, message } => {
+                write!(f, "invalid entry in {}: {}", path.display(), message)
+            }
+            StalkerError::InvalidInput(message) | StalkerError::NotFound(message) => {
+                f.write_str(message)
+            }
+            StalkerError::Editor(message) => write!(f, "editor failed: {}", message),
+            StalkerError::Watch(e) => write!(f, "cannot watch files: {}", e),
+            StalkerError::Daemon(message) => f.write_str(message),
+        }
+    }
+}
+
+impl Error for StalkerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            StalkerError::Io { source, .. } => Some(source),
+            StalkerError::Watch(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<notify::Error> for StalkerError {
+    fn from(e: notify::Error) -> StalkerError {
+        StalkerError::Watch(e)
+    }
+}
@@ -0,0 +1,126 @@
+use notify::DebouncedEvent;
+use std::ffi::OsString;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// What happened to a watched file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Write,
+    Create,
+    /// Another file was renamed to it. Renames over a file that was already
+    /// watched, as editors do on atomic saves, are writes instead.
+    Rename,
+    /// Nothing changed; the actions were run on request, e.g. by `stalk ctl trigger`.
+    Trigger,
+}
+
+impl ChangeKind {
+    /// Picks the events that count as a change to the file they name.
+    pub fn classify(event: DebouncedEvent) -> Option<(PathBuf, ChangeKind)> {
+        match event {
+            // notify reports a file that was deleted and recreated within the
+            // debounce time as a write, without a notice first.
+            DebouncedEvent::NoticeWrite(path) | DebouncedEvent::Write(path) => {
+                Some((path, ChangeKind::Write))
+            }
+            DebouncedEvent::Create(path) => Some((path, ChangeKind::Create)),
+            DebouncedEvent::Rename(_, path) => Some((path, ChangeKind::Rename)),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeKind::Write => "write",
+            ChangeKind::Create => "create",
+            ChangeKind::Rename => "rename",
+            ChangeKind::Trigger => "trigger",
+        }
+    }
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A change that actions are run for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    /// The changed file, as it is substituted for `{path}`.
+    pub path: PathBuf,
+    pub kind: ChangeKind,
+    /// The stalklist entry the file was found under.
+    pub root: PathBuf,
+    /// Every file the actions run for: `path` alone, or all the files that
+    /// changed while changes were held when the actions run once for them
+    /// (e.g. on resuming in `once` mode), ending with `path`.
+    pub paths: Vec<PathBuf>,
+}
+
+impl Change {
+    pub fn new(path: PathBuf, kind: ChangeKind, root: PathBuf) -> Change {
+        Change {
+            paths: vec![path.clone()],
+            path,
+            kind,
+            root,
+        }
+    }
+
+    /// The directory the changed file is in.
+    pub fn dir(&self) -> PathBuf {
+        self.path
+            .parent()
+            .map_or_else(|| self.root.clone(), Path::to_path_buf)
+    }
+
+    /// The variables exported to every action run for this change.
+    pub fn env(&self) -> [(&'static str, OsString); 5] {
+        // One per line, as file names can contain the usual list separators.
+        let mut paths = OsString::new();
+        for (index, path) in self.paths.iter().enumerate() {
+            if index > 0 {
+                paths.push("\n");
+            }
+            paths.push(path);
+        }
+        [
+            ("STALKER_PATH", self.path.clone().into_os_string()),
+            ("STALKER_PATHS", paths),
+            ("STALKER_DIR", self.dir().into_os_string()),
+            ("STALKER_EVENT", OsString::from(self.kind.as_str())),
+            ("STALKER_ROOT", self.root.clone().into_os_string()),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_with_and_without_a_notice_are_writes() {
+        let path = PathBuf::from("/w/a.txt");
+        for event in [
+            DebouncedEvent::NoticeWrite(path.clone()),
+            DebouncedEvent::Write(path.clone()),
+        ] {
+            assert_eq!(ChangeKind::classify(event), Some((path.clone(), ChangeKind::Write)));
+        }
+    }
+
+    #[test]
+    fn removals_and_metadata_changes_are_not_changes() {
+        let path = PathBuf::from("/w/a.txt");
+        for event in [
+            DebouncedEvent::NoticeRemove(path.clone()),
+            DebouncedEvent::Remove(path.clone()),
+            DebouncedEvent::Chmod(path),
+        ] {
+            assert_eq!(ChangeKind::classify(event), None);
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use crate::config::InstanceConfig;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// What the watcher loop should do with an incoming change.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Run,
+    /// The change was most likely made by one of our own actions.
+    Suppressed,
+    /// The file keeps retriggering itself; it is muted until the loop window passes.
+    LoopDetected(usize),
+    Muted,
+}
+
+/// What a file looked like, to tell whether an action wrote to it. `None`
+/// in place of a stamp means the file doesn't exist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Stamp {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+impl Stamp {
+    pub(crate) fn of(path: &Path) -> Option<Stamp> {
+        let metadata = fs::metadata(path).ok()?;
+        Some(Stamp {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+        })
+    }
+}
+
+// A file the actions changed, and what they left it as.
+struct Written {
+    stamp: Option<Stamp>,
+    until: Instant,
+}
+
+/// Keeps actions from retriggering themselves when they write to the file
+/// that triggered them (e.g. `rustfmt {path}`), or to other watched files
+/// that trigger them back in turn.
+pub struct FeedbackGuard {
+    suppress_window: Duration,
+    loop_threshold: usize,
+    loop_window: Duration,
+    before: HashMap<PathBuf, Option<Stamp>>,
+    written: HashMap<PathBuf, Written>,
+    muted_until: HashMap<PathBuf, Instant>,
+    triggers: HashMap<PathBuf, VecDeque<Instant>>,
+}
+
+impl FeedbackGuard {
+    pub fn new(config: &InstanceConfig) -> FeedbackGuard {
+        FeedbackGuard {
+            suppress_window: config.suppress_window,
+            loop_threshold: config.loop_threshold,
+            loop_window: config.loop_window,
+            before: HashMap::new(),
+            written: HashMap::new(),
+            muted_until: HashMap::new(),
+            triggers: HashMap::new(),
+        }
+    }
+
+    /// Takes the settings of a reloaded config, keeping what was recorded so far.
+    pub fn reconfigure(&mut self, config: &InstanceConfig) {
+        self.suppress_window = config.suppress_window;
+        self.loop_threshold = config.loop_threshold;
+        self.loop_window = config.loop_window;
+    }
+
+    /// Called for every change before actions run. A change is only put down
+    /// to the actions when they changed the file and it still looks like they
+    /// left it; such changes are suppressed during the suppress window and
+    /// count towards a loop after it.
+    pub fn check(&mut self, path: &Path) -> Verdict {
+        let now = Instant::now();
+
+        if matches!(self.muted_until.get(path), Some(until) if now < *until) {
+            return Verdict::Muted;
+        }
+
+        let own = match self.written.remove(path) {
+            Some(written) if written.stamp == Stamp::of(path) => written,
+            _ => {
+                // Someone else changed the file, which ends any loop.
+                self.triggers.remove(path);
+                return Verdict::Run;
+            }
+        };
+        if now < own.until {
+            self.written.insert(path.to_path_buf(), own);
+            return Verdict::Suppressed;
+        }
+
+        let history = self.triggers.entry(path.to_path_buf()).or_default();
+        while matches!(history.front(), Some(t) if now.duration_since(*t) > self.loop_window) {
+            history.pop_front();
+        }
+        history.push_back(now);
+
+        // A threshold of 0 disables loop detection.
+        if self.loop_threshold > 0 && history.len() > self.loop_threshold {
+            let count = history.len();
+            history.clear();
+            self.muted_until
+                .insert(path.to_path_buf(), now + self.loop_window);
+            return Verdict::LoopDetected(count);
+        }
+        Verdict::Run
+    }
+
+    /// Called before actions run, with every watched path they may write to.
+    pub fn started<'a>(&mut self, watched: impl IntoIterator<Item = &'a Path>) {
+        self.before = watched
+            .into_iter()
+            .map(|path| (path.to_path_buf(), Stamp::of(path)))
+            .collect();
+    }
+
+    /// Called once the actions have finished, to note which of the paths
+    /// passed to `started` they changed.
+    pub fn finished(&mut self) {
+        let until = Instant::now() + self.suppress_window;
+        for (path, before) in self.before.drain() {
+            let after = Stamp::of(&path);
+            if before != after {
+                self.written.insert(path, Written { stamp: after, until });
+            }
+        }
+    }
+
+    pub fn loop_window(&self) -> Duration {
+        self.loop_window
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn guard(suppress_window: Duration, loop_threshold: usize) -> FeedbackGuard {
+        FeedbackGuard::new(&InstanceConfig {
+            suppress_window,
+            loop_threshold,
+            ..InstanceConfig::default()
+        })
+    }
+
+    // Runs "actions" on `path`, which write `written` to it when given.
+    fn run(guard: &mut FeedbackGuard, path: &Path, written: Option<&str>) {
+        guard.started([path]);
+        if let Some(content) = written {
+            fs::write(path, content).unwrap();
+        }
+        guard.finished();
+    }
+
+    #[test]
+    fn edits_are_not_suppressed_when_the_action_leaves_the_file_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.txt");
+        fs::write(&path, "1").unwrap();
+        let mut guard = guard(Duration::from_secs(60), 5);
+
+        assert_eq!(guard.check(&path), Verdict::Run);
+        run(&mut guard, &path, None);
+        fs::write(&path, "22").unwrap();
+        assert_eq!(guard.check(&path), Verdict::Run);
+    }
+
+    #[test]
+    fn the_actions_own_change_is_suppressed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.txt");
+        fs::write(&path, "1").unwrap();
+        let mut guard = guard(Duration::from_secs(60), 5);
+
+        assert_eq!(guard.check(&path), Verdict::Run);
+        run(&mut guard, &path, Some("formatted"));
+        assert_eq!(guard.check(&path), Verdict::Suppressed);
+
+        // An edit after the action's change runs again.
+        fs::write(&path, "edited by hand").unwrap();
+        assert_eq!(guard.check(&path), Verdict::Run);
+    }
+
+    #[test]
+    fn only_retriggers_by_the_action_count_towards_a_loop() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.txt");
+        let mut guard = guard(Duration::ZERO, 3);
+
+        for i in 0..10 {
+            fs::write(&path, "x".repeat(i + 1)).unwrap();
+            assert_eq!(guard.check(&path), Verdict::Run);
+            run(&mut guard, &path, None);
+        }
+
+        fs::write(&path, "start").unwrap();
+        let mut verdicts = Vec::new();
+        for i in 0..4 {
+            verdicts.push(guard.check(&path));
+            run(&mut guard, &path, Some(&"y".repeat(i + 1)));
+        }
+        verdicts.push(guard.check(&path));
+        assert_eq!(
+            verdicts,
+            [
+                Verdict::Run,
+                Verdict::Run,
+                Verdict::Run,
+                Verdict::Run,
+                Verdict::LoopDetected(4)
+            ]
+        );
+        assert_eq!(guard.check(&path), Verdict::Muted);
+    }
+
+    #[test]
+    fn actions_writing_to_each_others_files_do_not_loop() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a.txt");
+        let b = dir.path().join("b.txt");
+        fs::write(&a, "1").unwrap();
+        fs::write(&b, "1").unwrap();
+        let watched = [a.as_path(), b.as_path()];
+        let mut guard = guard(Duration::from_secs(60), 5);
+
+        // The action for a.txt writes b.txt, and the one for b.txt writes a.txt.
+        assert_eq!(guard.check(&a), Verdict::Run);
+        guard.started(watched);
+        fs::write(&b, "from a").unwrap();
+        guard.finished();
+        assert_eq!(guard.check(&b), Verdict::Suppressed);
+
+        // Changes to files the actions didn't touch still run.
+        fs::write(&a, "edited by hand").unwrap();
+        assert_eq!(guard.check(&a), Verdict::Run);
+    }
+}
@@ -0,0 +1,47 @@
+use glob::Pattern;
+use std::path::Path;
+
+/// File names written by editors next to the file being edited. None of them
+/// are worth running actions for.
+pub const EDITOR_PATTERNS: &[&str] = &[
+    // Vim swap and backup files, and the file Vim creates to test if a directory is writable.
+    "*.swp",
+    "*.swo",
+    "*.swx",
+    "*~",
+    "4913",
+    // Emacs auto-save files and lock files.
+    "#*#",
+    ".#*",
+    // JetBrains IDEs' safe-write files.
+    "*___jb_tmp___",
+    "*___jb_old___",
+    // Atomic-save temp files (VS Code, Sublime Text, GNOME editors, ...).
+    "*.tmp",
+    "*.sublime-tmp",
+    ".goutputstream-*",
+];
+
+/// Decides which file names are editor artifacts rather than real changes.
+pub struct EditorFilter {
+    patterns: Vec<Pattern>,
+}
+
+impl EditorFilter {
+    /// Builds the filter from the built-in patterns (unless disabled) plus the user's own.
+    pub fn new(builtin: bool, extra: &[String]) -> Result<EditorFilter, glob::PatternError> {
+        let builtin = EDITOR_PATTERNS.iter().filter(|_| builtin).copied();
+        let patterns = builtin
+            .chain(extra.iter().map(String::as_str))
+            .map(Pattern::new)
+            .collect::<Result<_, _>>()?;
+        Ok(EditorFilter { patterns })
+    }
+
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => self.patterns.iter().any(|p| p.matches(name)),
+            None => false,
+        }
+    }
+}
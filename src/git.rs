@@ -0,0 +1,284 @@
+use notify::{raw_watcher, RawEvent, RecursiveMode, Watcher};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// What git keeps in its directory while a rebase or merge is in progress.
+const IN_PROGRESS: [&str; 3] = ["rebase-merge", "rebase-apply", "MERGE_HEAD"];
+
+/// Locks git takes when it moves HEAD, as a checkout, reset, merge or pull
+/// does after rewriting the work tree. Commands such as `git status`, which
+/// editors run all the time, never take them.
+const HEAD_LOCKS: [&str; 2] = ["HEAD.lock", "ORIG_HEAD.lock"];
+
+const INDEX_LOCK: &str = "index.lock";
+
+/// How long index.lock has to stay before it counts as an operation that
+/// rewrites files, e.g. a large `git restore`. `git status` takes it too,
+/// but only briefly.
+const INDEX_LOCK_PERSISTS: Duration = Duration::from_secs(1);
+
+// HEAD locks are seen by watching the git directories; this is how often
+// the markers that stay around are checked.
+const CHECK: Duration = Duration::from_millis(250);
+
+/// A git work tree and the directory git keeps its state in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Repo {
+    pub work_tree: PathBuf,
+    pub git_dir: PathBuf,
+}
+
+impl Repo {
+    /// The repository `path` is in, if any.
+    pub fn find(path: &Path) -> Option<Repo> {
+        path.ancestors().find_map(|dir| {
+            let dot_git = dir.join(".git");
+            let git_dir = if dot_git.is_dir() {
+                dot_git
+            } else {
+                // Linked work trees and submodules have a file pointing at it.
+                let content = fs::read_to_string(&dot_git).ok()?;
+                dir.join(content.strip_prefix("gitdir:")?.trim())
+            };
+            Some(Repo {
+                work_tree: dir.to_path_buf(),
+                git_dir,
+            })
+        })
+    }
+
+    /// Whether a rebase or merge is in progress.
+    pub fn in_progress(&self) -> bool {
+        IN_PROGRESS
+            .iter()
+            .any(|marker| fs::symlink_metadata(self.git_dir.join(marker)).is_ok())
+    }
+
+    fn has(&self, path: &Path, names: &[&str]) -> bool {
+        path.parent() == Some(self.git_dir.as_path())
+            && path
+                .file_name()
+                .is_some_and(|name| names.iter().any(|wanted| name == *wanted))
+    }
+}
+
+// What the monitor thread knows about a repository.
+#[derive(Default)]
+struct RepoState {
+    last_busy: Option<Instant>,
+    index_locked_since: Option<Instant>,
+}
+
+/// Keeps track of git operations in the repositories of the stalklist
+/// entries, on a thread of its own.
+pub struct GitMonitor {
+    repos: Vec<(Repo, Arc<Mutex<RepoState>>)>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl GitMonitor {
+    pub fn new(paths: &[PathBuf]) -> GitMonitor {
+        GitMonitor::watching(paths, &[])
+    }
+
+    /// A monitor for the repositories of `paths` that carries on with what
+    /// this one knows about the repositories it watches too, such as an
+    /// operation that is still going on.
+    pub fn renewed(&self, paths: &[PathBuf]) -> GitMonitor {
+        GitMonitor::watching(paths, &self.repos)
+    }
+
+    fn watching(paths: &[PathBuf], known: &[(Repo, Arc<Mutex<RepoState>>)]) -> GitMonitor {
+        let mut repos: Vec<(Repo, Arc<Mutex<RepoState>>)> = Vec::new();
+        for repo in paths.iter().filter_map(|path| Repo::find(path)) {
+            if !repos.iter().any(|(watched, _)| *watched == repo) {
+                let state = known
+                    .iter()
+                    .find(|(watched, _)| *watched == repo)
+                    .map(|(_, state)| Arc::clone(state))
+                    .unwrap_or_default();
+                repos.push((repo, state));
+            }
+        }
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = if repos.is_empty() {
+            None
+        } else {
+            let repos = repos.clone();
+            let stop = Arc::clone(&stop);
+            // Watching starts before `new` returns, so nothing is missed.
+            let (tx, rx) = channel();
+            let watcher = raw_watcher(tx).ok().map(|mut watcher| {
+                for (repo, _) in &repos {
+                    let _ = watcher.watch(&repo.git_dir, RecursiveMode::NonRecursive);
+                }
+                watcher
+            });
+            thread::Builder::new()
+                .name(String::from("stalker-git"))
+                .spawn(move || {
+                    // Without a watcher only the markers that stay around are seen.
+                    let _watcher = watcher;
+                    monitor(&repos, &rx, &stop)
+                })
+                .ok()
+        };
+        GitMonitor {
+            repos,
+            stop,
+            thread,
+        }
+    }
+
+    /// The watched repository `path` is in, if any.
+    pub fn repo_of(&self, path: &Path) -> Option<&Repo> {
+        self.repos
+            .iter()
+            .map(|(repo, _)| repo)
+            .filter(|repo| path.starts_with(&repo.work_tree))
+            // The innermost one, for submodules.
+            .max_by_key(|repo| repo.work_tree.components().count())
+    }
+
+    /// Whether a git operation was in progress in `repo` within the last
+    /// `settle`, which should cover how late the event source delivers events.
+    pub fn is_busy(&self, repo: &Repo, settle: Duration) -> bool {
+        self.repos
+            .iter()
+            .find(|(known, _)| known == repo)
+            .and_then(|(_, state)| state.lock().unwrap_or_else(PoisonError::into_inner).last_busy)
+            .is_some_and(|last_busy| last_busy.elapsed() < settle)
+    }
+}
+
+impl Drop for GitMonitor {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn monitor(repos: &[(Repo, Arc<Mutex<RepoState>>)], rx: &Receiver<RawEvent>, stop: &AtomicBool) {
+    while !stop.load(Ordering::SeqCst) {
+        let mut touched: Vec<PathBuf> = Vec::new();
+        if let Ok(event) = rx.recv_timeout(CHECK) {
+            touched.extend(event.path);
+            touched.extend(rx.try_iter().filter_map(|event| event.path));
+        }
+        let now = Instant::now();
+        for (repo, state) in repos {
+            let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+            state.index_locked_since = if fs::symlink_metadata(repo.git_dir.join(INDEX_LOCK)).is_ok() {
+                state.index_locked_since.or(Some(now))
+            } else {
+                None
+            };
+            let index_lock_persists = state
+                .index_locked_since
+                .is_some_and(|since| now.duration_since(since) >= INDEX_LOCK_PERSISTS);
+            if index_lock_persists
+                || repo.in_progress()
+                || touched.iter().any(|path| repo.has(path, &HEAD_LOCKS))
+            {
+                state.last_busy = Some(now);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repo() -> (tempfile::TempDir, Repo) {
+        let dir = tempfile::tempdir().unwrap();
+        let work_tree = fs::canonicalize(dir.path()).unwrap();
+        fs::create_dir_all(work_tree.join(".git")).unwrap();
+        fs::create_dir_all(work_tree.join("src")).unwrap();
+        let repo = Repo {
+            git_dir: work_tree.join(".git"),
+            work_tree,
+        };
+        (dir, repo)
+    }
+
+    // Waits a little longer than the monitor takes to notice anything.
+    fn busy_soon(monitor: &GitMonitor, repo: &Repo) -> bool {
+        let deadline = Instant::now() + CHECK * 4;
+        while Instant::now() < deadline {
+            if monitor.is_busy(repo, Duration::from_secs(60)) {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    #[test]
+    fn repositories_are_found_from_paths_inside_them() {
+        let (_dir, repo) = repo();
+        assert_eq!(Repo::find(&repo.work_tree.join("src/main.rs")), Some(repo.clone()));
+
+        // A linked work tree points at its git directory.
+        let linked = repo.work_tree.join("src/linked");
+        fs::create_dir(&linked).unwrap();
+        fs::write(linked.join(".git"), "gitdir: /elsewhere/.git/worktrees/linked\n").unwrap();
+        assert_eq!(
+            Repo::find(&linked.join("a.txt")).unwrap().git_dir,
+            PathBuf::from("/elsewhere/.git/worktrees/linked")
+        );
+    }
+
+    #[test]
+    fn moving_head_is_an_operation() {
+        let (_dir, repo) = repo();
+        let monitor = GitMonitor::new(std::slice::from_ref(&repo.work_tree));
+        assert_eq!(monitor.repo_of(&repo.work_tree.join("src/a.txt")), Some(&repo));
+
+        fs::write(repo.git_dir.join("HEAD.lock"), "").unwrap();
+        fs::remove_file(repo.git_dir.join("HEAD.lock")).unwrap();
+        assert!(busy_soon(&monitor, &repo));
+    }
+
+    #[test]
+    fn an_operation_outlasts_renewing_the_monitor() {
+        let (_dir, repo) = repo();
+        let monitor = GitMonitor::new(std::slice::from_ref(&repo.work_tree));
+        fs::write(repo.git_dir.join("HEAD.lock"), "").unwrap();
+        fs::remove_file(repo.git_dir.join("HEAD.lock")).unwrap();
+        assert!(busy_soon(&monitor, &repo));
+
+        let renewed = monitor.renewed(std::slice::from_ref(&repo.work_tree));
+        drop(monitor);
+        assert!(renewed.is_busy(&repo, Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn a_brief_index_lock_is_not_an_operation() {
+        let (_dir, repo) = repo();
+        let monitor = GitMonitor::new(std::slice::from_ref(&repo.work_tree));
+
+        fs::write(repo.git_dir.join("index.lock"), "").unwrap();
+        thread::sleep(Duration::from_millis(20));
+        fs::remove_file(repo.git_dir.join("index.lock")).unwrap();
+        assert!(!busy_soon(&monitor, &repo));
+    }
+
+    #[test]
+    fn a_merge_in_progress_is_an_operation() {
+        let (_dir, repo) = repo();
+        fs::write(repo.git_dir.join("MERGE_HEAD"), "").unwrap();
+        assert!(repo.in_progress());
+        let monitor = GitMonitor::new(std::slice::from_ref(&repo.work_tree));
+        assert!(busy_soon(&monitor, &repo));
+    }
+}
@@ -1,660 +1,424 @@
-use crossterm::execute;
-use crossterm::style::{Color, Print, ResetColor, SetForegroundColor};
-use execute::{command, Execute};
-use notify::{watcher, DebouncedEvent, RecursiveMode, Watcher};
-use std::fs::File;
-use std::io::{stdout, BufRead, BufReader, Write};
-use std::path::PathBuf;
-use std::process::Stdio;
-use std::sync::mpsc::channel;
-use std::time::Duration;
-use std::{
-    fs::{self, OpenOptions},
-    path::Path,
+use action::{next_id, read_actionlist, write_actionlist, ActionEntry};
+pub use control::{
+    handle_signals, listen as listen_control, send as send_control, ControlServer, Controller,
+    PauseMode, RunningAction,
 };
-use walkdir::WalkDir;
-
-pub fn create_stalker_dir(path: &Path) {
-    match fs::create_dir_all(path) {
-        Ok(_) => {
-            match execute!(
-                stdout(),
-                SetForegroundColor(Color::Green),
-                Print(format!("Successfully created stalker instance at {}\n", path.display())),
-                ResetColor
-                ) {
-                Ok(_) => {},
-                Err(e) => eprintln!("Error printing stalker directory creation output on create_dir_all function: {}", e)
-            }
-        }
-        Err(e) => {
-            match execute!(
-                stdout(),
-                SetForegroundColor(Color::Green),
-                Print(format!("Error creating stalker instance: {}\n", e)),
-                ResetColor
-                ) {
-                Ok(_) => {},
-                Err(e) => eprintln!("Error printing stalker directory creation error output on create_dir_all function: {}", e)
-            }
-        }
+use entry::{read_stalklist, write_stalklist, WatchEntry};
+use error::StalkerError;
+use notice::Notice;
+pub use runner::{Stalker, StalkerBuilder, StalkerHandle};
+pub use source::{EventSource, Next, NotifySource, PollSource, ScriptedSource};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+
+pub mod action;
+pub mod config;
+pub(crate) mod control;
+pub mod daemon;
+pub mod dotenv;
+pub mod entry;
+pub mod error;
+pub mod event;
+pub(crate) mod feedback;
+pub mod filter;
+pub(crate) mod git;
+pub mod notice;
+pub mod paths;
+pub mod report;
+pub mod runner;
+pub(crate) mod source;
+pub mod symlink;
+pub(crate) mod watch;
+
+fn require_instance(stalker_instance: &Path) -> Result<(), StalkerError> {
+    if stalker_instance.is_dir() {
+        Ok(())
+    } else {
+        Err(StalkerError::NoInstance(stalker_instance.to_path_buf()))
     }
 }
 
-pub fn create_stalk_list(stalker_instance: &Path) {
-    match fs::File::create(stalker_instance.join("stalklist.txt")) {
-        Ok(_) => {
-            match execute!(
-                stdout(),
-                SetForegroundColor(Color::Green),
-                Print("Successfully created stalklist\n"),
-                ResetColor
-            ) {
-                Ok(_) => {}
-                Err(e) => eprintln!(
-                    "Error printing stalklist creation output on create_stalk_list function: {}",
-                    e
-                ),
-            }
-        }
-        Err(e) => {
-            match execute!(
-                stdout(),
-                SetForegroundColor(Color::Red),
-                Print(format!("Error creating stalklist: {}\n", e)),
-                ResetColor
-                ) {
-                Ok(_) => {},
-                Err(e) => eprintln!("Error printing stalklist creation error output on create_stalk_list function: {}", e)
-            }
-        }
-    }
+pub fn create_stalker_dir(path: &Path) -> Result<(), StalkerError> {
+    fs::create_dir_all(path).map_err(|e| StalkerError::io(path, e))
+}
+
+pub fn create_stalk_list(stalker_instance: &Path) -> Result<(), StalkerError> {
+    require_instance(stalker_instance)?;
+    let path = stalker_instance.join("stalklist.txt");
+    fs::File::create(&path)
+        .map(|_| ())
+        .map_err(|e| StalkerError::io(&path, e))
 }
 
-pub fn update_stalk_list(stalker_instance: &Path, input_path: &String) {
-    match OpenOptions::new()
+/// What `update_stalk_list` did with an entry, which carries its normalized path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StalklistUpdate {
+    Added(WatchEntry),
+    /// The path was already in the stalklist, so nothing was written.
+    AlreadyListed(WatchEntry),
+}
+
+pub fn update_stalk_list(stalker_instance: &Path, entry: &WatchEntry) -> Result<StalklistUpdate, StalkerError> {
+    require_instance(stalker_instance)?;
+    let path = paths::normalize(&entry.path).map_err(|e| {
+        StalkerError::InvalidInput(format!("cannot add {}: {}", entry.path.display(), e))
+    })?;
+    let entry = WatchEntry { path, ..entry.clone() };
+
+    if read_stalklist(stalker_instance)?.iter().any(|e| e.path == entry.path) {
+        return Ok(StalklistUpdate::AlreadyListed(entry));
+    }
+
+    let stalklist = stalker_instance.join("stalklist.txt");
+    // file variable is made mutable because write! macro takes a mutable handle
+    let mut file = OpenOptions::new()
         .append(true)
-        .open(stalker_instance.join("stalklist.txt"))
-    {
-        // file variable is made mutable because write! macro takes a mutable handle
-        Ok(mut file) => {
-            match writeln!(file, "{}", input_path) {
-                Ok(_) => {
-                    match execute!(
-                        stdout(),
-                        SetForegroundColor(Color::Green),
-                        Print(format!("Successfully added {} to stalklist\n", input_path)),
-                        ResetColor
-                        ) {
-                        Ok(_) => {},
-                        Err(e) => eprintln!("Error printing stalklist update output on update_stalk_list function: {}", e)
-                    }
-                }
-                Err(e) => {
-                    match execute!(
-                        stdout(),
-                        SetForegroundColor(Color::Red),
-                        Print(format!("Error adding {} to stalklist: {}\n", input_path, e)),
-                        ResetColor
-                        ) {
-                        Ok(_) => {},
-                        Err(e) => eprintln!("Error printing stalklist update output on update_stalk_list function: {}", e)
-                    }
-                }
+        .open(&stalklist)
+        .map_err(|e| StalkerError::io(&stalklist, e))?;
+    writeln!(file, "{}", entry.to_line()).map_err(|e| StalkerError::io(&stalklist, e))?;
+    Ok(StalklistUpdate::Added(entry))
+}
+
+pub fn list_action_list(stalker_instance: &Path) -> Result<Vec<ActionEntry>, StalkerError> {
+    require_instance(stalker_instance)?;
+    read_actionlist(stalker_instance)
+}
+
+/// The stalklist entries, in the order their 1-based indexes refer to.
+pub fn list_stalk_list(stalker_instance: &Path) -> Result<Vec<WatchEntry>, StalkerError> {
+    require_instance(stalker_instance)?;
+    read_stalklist(stalker_instance)
+}
+
+/// What a removal took out of a list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Removed<T> {
+    pub removed: Vec<T>,
+    /// Arguments that matched nothing.
+    pub unmatched: Vec<String>,
+}
+
+/// Removes stalklist entries by index (as shown by `stalk list`), by path, or
+/// by a glob pattern over paths.
+pub fn remove_from_stalklist(stalker_instance: &Path, path_to_remove: &[&str]) -> Result<Removed<WatchEntry>, StalkerError> {
+    require_instance(stalker_instance)?;
+    let stalklist_item = read_stalklist(stalker_instance)?;
+    if stalklist_item.is_empty() {
+        return Err(StalkerError::NotFound(String::from("the stalklist is empty")));
+    }
+
+    let mut removed = vec![false; stalklist_item.len()];
+    let mut unmatched = Vec::new();
+    for &item in path_to_remove {
+        let normalized = paths::normalize(Path::new(item)).ok();
+        let matched = select(item, &stalklist_item, |index, entry| {
+            match item.parse::<usize>() {
+                Ok(n) => Selected::Numbered(n == index + 1),
+                Err(_) if entry.path == Path::new(item) || Some(&entry.path) == normalized.as_ref() => Selected::Exact,
+                Err(_) => Selected::Glob(
+                    [Some(PathBuf::from(item)), normalized.clone()]
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|p| glob::Pattern::new(&p.to_string_lossy()).ok())
+                        .any(|pattern| pattern.matches_path(&entry.path)),
+                ),
             }
+        });
+        if matched.is_empty() {
+            unmatched.push(item.to_string());
         }
-        Err(e) => {
-            match execute!(
-                    stdout(),
-                    SetForegroundColor(Color::Red),
-                    Print(format!("Error opening stalklist at {}: {}\n", stalker_instance.display(), e)),
-                    ResetColor
-                    ) {
-                    Ok(_) => {},
-                    Err(e) => eprintln!("Error printing stalklist update file opening error output on update_stalk_list function: {}", e)
-                }
+        for i in matched {
+            removed[i] = true;
         }
     }
+
+    let (gone, remaining) = partition(stalklist_item, &removed);
+    if !gone.is_empty() {
+        write_stalklist(stalker_instance, &remaining)?;
+    }
+    Ok(Removed { removed: gone, unmatched })
 }
 
-pub fn list_action_list(stalker_instance: &Path) {
-    match fs::File::open(stalker_instance.join("actionlist.txt")) {
-        Ok(file) => {
-            for line in BufReader::new(file).lines() {
-                match line {
-                    Ok(item) => {
-                        match execute!(
-                            stdout(),
-                            SetForegroundColor(Color::Blue),
-                            Print(format!("{}\n", item)),
-                            ResetColor
-                            ) {
-                            Ok(_) => {},
-                            Err(e) => eprintln!("Error printing actionlist item output on list_action_list function: {}", e)
-                        }
-                    }
-                    Err(e) => {
-                        match execute!(
-                            stdout(),
-                            SetForegroundColor(Color::Red),
-                            Print(format!("Error reading line(s): {}\n", e)),
-                            ResetColor
-                            ) {
-                            Ok(_) => {},
-                            Err(e) => eprintln!("Error printing actionlist item error output on list_action_list function: {}", e)
-                        }
-                    }
-                }
-            }
-        }
-        Err(e) => {
-            match execute!(
-                stdout(),
-                SetForegroundColor(Color::Red),
-                Print(format!("Error opening actionlist at {}: {}\n", stalker_instance.display(), e)),
-                ResetColor
-                )
-            {
-                Ok(_) => {},
-                Err(e) => eprintln!("Error printing actionlist opening error output on list_action_list function: {}", e)
-            }
-        }
+/// How a single list item relates to a removal argument.
+enum Selected {
+    /// Matched (or not) by index or ID.
+    Numbered(bool),
+    Exact,
+    Glob(bool),
+}
+
+// Exact matches win over glob matches, so a command or path that happens to
+// contain `*` can still be removed on its own.
+fn select<T>(item: &str, items: &[T], matches: impl Fn(usize, &T) -> Selected) -> Vec<usize> {
+    let results: Vec<Selected> = items.iter().enumerate().map(|(i, t)| matches(i, t)).collect();
+    let exact: Vec<usize> = results
+        .iter()
+        .enumerate()
+        .filter(|(_, r)| matches!(r, Selected::Numbered(true) | Selected::Exact))
+        .map(|(i, _)| i)
+        .collect();
+    if !exact.is_empty() || !item.contains(['*', '?', '[']) {
+        return exact;
     }
+    results
+        .iter()
+        .enumerate()
+        .filter(|(_, r)| matches!(r, Selected::Glob(true)))
+        .map(|(i, _)| i)
+        .collect()
 }
 
-pub fn list_stalk_list(stalker_instance: &Path) {
-    match fs::File::open(stalker_instance.join("stalklist.txt")) {
-        Ok(file) => {
-            for line in BufReader::new(file).lines() {
-                match line {
-                    Ok(item) => {
-                        match execute!(
-                            stdout(),
-                            SetForegroundColor(Color::Blue),
-                            Print(format!("{}\n", item)),
-                            ResetColor
-                            ){
-                            Ok(_) => {},
-                            Err(e) => eprintln!("Error printing stalklist item output on list_stalk_list function: {}", e)
-                        }
-                    }
-                    Err(e) => {
-                        match execute!(
-                            stdout(),
-                            SetForegroundColor(Color::Red),
-                            Print(format!("Error reading line(s): {}\n", e)),
-                            ResetColor
-                            ) {
-                            Ok(_) => {},
-                            Err(e) => eprintln!("Error printing stalklist item error output on list_stalk_list function: {}", e)
-                        }
-                    }
-                }
-            }
-        }
-        Err(e) => {
-            match execute!(
-                stdout(),
-                SetForegroundColor(Color::Red),
-                Print(format!(
-                    "Error opening stalklist at {}: {}\n",
-                    stalker_instance.display(),
-                    e
-                )),
-                ResetColor
-            ) {
-                Ok(_) => {}
-                Err(e) => eprintln!(
-                    "Error printing stalklist opening error output on list_stalk_list function: {}",
-                    e
-                ),
-            }
-        }
+// Splits `items` into the removed ones and the ones that stay, keeping their order.
+fn partition<T>(items: Vec<T>, removed: &[bool]) -> (Vec<T>, Vec<T>) {
+    let (gone, remaining): (Vec<_>, Vec<_>) = items.into_iter().zip(removed).partition(|(_, removed)| **removed);
+    (
+        gone.into_iter().map(|(item, _)| item).collect(),
+        remaining.into_iter().map(|(item, _)| item).collect(),
+    )
+}
+
+pub fn create_commands(stalker_instance: &Path) -> Result<(), StalkerError> {
+    require_instance(stalker_instance)?;
+    let path = stalker_instance.join("actionlist.txt");
+    fs::File::create(&path)
+        .map(|_| ())
+        .map_err(|e| StalkerError::io(&path, e))
+}
+
+/// Adds `action` to the actionlist with a new ID, at the 1-based `position`
+/// or at the end. Returns the action as stored, with its ID.
+pub fn update_commands(stalker_instance: &Path, action: &ActionEntry, position: Option<usize>) -> Result<ActionEntry, StalkerError> {
+    require_instance(stalker_instance)?;
+    let mut actions = read_actionlist(stalker_instance)?;
+
+    let action = ActionEntry {
+        id: Some(next_id(&actions)),
+        ..action.clone()
+    };
+    let index = position.map_or(actions.len(), |p| p.saturating_sub(1).min(actions.len()));
+    actions.insert(index, action.clone());
+    write_actionlist(stalker_instance, &actions)?;
+    Ok(action)
+}
+
+/// Replaces the command of action `id`, keeping its options. Without a new
+/// command, the current one is opened in $VISUAL/$EDITOR. Returns the edited
+/// action, or `None` if the command was left unchanged.
+pub fn edit_action(stalker_instance: &Path, id: u32, command: Option<&str>) -> Result<Option<ActionEntry>, StalkerError> {
+    require_instance(stalker_instance)?;
+    let mut actions = read_actionlist(stalker_instance)?;
+    let action = actions
+        .iter_mut()
+        .find(|a| a.id == Some(id))
+        .ok_or_else(|| StalkerError::NotFound(format!("no action with ID {}", id)))?;
+
+    let edited = match command {
+        Some(command) => command.to_string(),
+        None => edit_in_editor(stalker_instance, &action.command, id).map_err(StalkerError::Editor)?,
+    };
+    if edited.is_empty() || edited == action.command {
+        return Ok(None);
     }
+    action.command = edited;
+    let edited = action.clone();
+    write_actionlist(stalker_instance, &actions)?;
+    Ok(Some(edited))
 }
 
-pub fn remove_from_stalklist(stalker_instance: &Path, path_to_remove: Vec<&String>) {
-    let mut stalklist_item: Vec<String> = Vec::new();
-
-    match fs::File::open(stalker_instance.join("stalklist.txt")) {
-        Ok(file) => {
-            for line in BufReader::new(file).lines() {
-                match line {
-                    Ok(item) => stalklist_item.push(item),
-                    Err(e) => {
-                        match execute!(
-                            stdout(),
-                            SetForegroundColor(Color::Red),
-                            Print(format!("Error reading line(s): {}\n", e)),
-                            ResetColor
-                            ) {
-                            Ok(_) => {},
-                            Err(e) => eprintln!("Error printing stalklist item removal error output on remove_from_stalklist function: {}", e)
-                        }
-                    }
-                }
-            }
-        }
-        Err(e) => {
-            match execute!(
-                stdout(),
-                SetForegroundColor(Color::Red),
-                Print(format!("Error opening stalklist at {}: {}\n", stalker_instance.join("stalklist.txt").display(), e)),
-                ResetColor
-                ) {
-                Ok(_) => {},
-                Err(e) => eprintln!("Error printing stalklist opening error output on remove_from_stalklist function: {}", e)
-            }
-        }
+fn edit_in_editor(stalker_instance: &Path, command: &str, id: u32) -> Result<String, String> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| String::from(if cfg!(windows) { "notepad" } else { "vi" }));
+    let argv = shell_words::split(&editor).map_err(|e| format!("cannot parse editor '{}': {}", editor, e))?;
+    let (program, args) = argv.split_first().ok_or("no editor set")?;
+
+    // Kept in the instance rather than a shared temp directory, and never
+    // opened if it exists already, so nobody else can put a file or a
+    // symlink in its place. The pid keeps concurrent edits apart.
+    let file = stalker_instance.join(format!("action-{}-{}.txt", id, process::id()));
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&file)
+        .and_then(|mut opened| writeln!(opened, "{}", command))
+        .map_err(|e| format!("cannot create {}: {}", file.display(), e))?;
+    let status = process::Command::new(program)
+        .args(args)
+        .arg(&file)
+        .status()
+        .map_err(|e| format!("cannot run {}: {}", editor, e));
+    let content = fs::read_to_string(&file).map_err(|e| e.to_string());
+    let _ = fs::remove_file(&file);
+
+    if !status?.success() {
+        return Err(format!("{} exited with an error", editor));
     }
+    let content = content?;
+    let mut lines = content.lines().map(str::trim).filter(|line| !line.is_empty());
+    match (lines.next(), lines.next()) {
+        (Some(line), None) => Ok(line.to_string()),
+        (None, _) => Ok(String::new()),
+        (Some(_), Some(_)) => Err(String::from("an action has to be a single line")),
+    }
+}
 
-    if !stalker_instance.join("stalklist.txt").exists() {
-        match execute!(
-            stdout(),
-            SetForegroundColor(Color::Red),
-            Print(format!("Error opening stalklist at {}: Cannot find stalklist.txt\n", stalker_instance.join("stalklist.txt").display())),
-            ResetColor
-            ) {
-            Ok(_) => {},
-            Err(e) => eprintln!("Error printing stalklist existence checking error output on remove_from_stalklist function: {}", e)
-        }
-        return;
-    } else if stalklist_item.is_empty() {
-        match execute!(
-            stdout(),
-            SetForegroundColor(Color::Red),
-            Print(format!("Error deleting item from stalklist at {}: stalklist.txt is empty\n", stalker_instance.join("stalklist.txt").display())),
-            ResetColor
-            ) {
-            Ok(_) => {},
-            Err(e) => eprintln!("Error printing stalklist empty checking error output on remove_from_stalklist function: {}", e)
-        }
-        return;
+/// Moves action `id` to the 1-based `position` in the actionlist. Returns
+/// the position it ended up at, which is the last one if `position` is
+/// past the end.
+pub fn move_action(stalker_instance: &Path, id: u32, position: usize) -> Result<usize, StalkerError> {
+    require_instance(stalker_instance)?;
+    let mut actions = read_actionlist(stalker_instance)?;
+    let index = actions
+        .iter()
+        .position(|a| a.id == Some(id))
+        .ok_or_else(|| StalkerError::NotFound(format!("no action with ID {}", id)))?;
+    let action = actions.remove(index);
+    let index = position.saturating_sub(1).min(actions.len());
+    actions.insert(index, action);
+    write_actionlist(stalker_instance, &actions)?;
+    Ok(index + 1)
+}
+
+/// Removes actions by ID (as shown by `stalk list-action`), by their exact
+/// command, or by a glob pattern over commands.
+pub fn remove_from_actionlist(stalker_instance: &Path, action_to_remove: &[&str]) -> Result<Removed<ActionEntry>, StalkerError> {
+    require_instance(stalker_instance)?;
+    let action_item = read_actionlist(stalker_instance)?;
+    if action_item.is_empty() {
+        return Err(StalkerError::NotFound(String::from("the actionlist is empty")));
     }
 
-    for item in path_to_remove {
-        stalklist_item.retain(|i| i != item);
-        match execute!(
-            stdout(),
-            SetForegroundColor(Color::Green),
-            Print(format!("Successfully removed {} from stalklist\n", item)),
-            ResetColor
-        ) {
-            Ok(_) => {}
-            Err(e) => eprintln!(
-                "Error printing remove stalklist item output on remove_from_stalklist function: {}",
-                e
-            ),
+    let mut removed = vec![false; action_item.len()];
+    let mut unmatched = Vec::new();
+    for &item in action_to_remove {
+        let matched = select(item, &action_item, |_, action| match item.parse::<u32>() {
+            Ok(id) => Selected::Numbered(action.id == Some(id)),
+            Err(_) if action.command == item => Selected::Exact,
+            Err(_) => Selected::Glob(glob::Pattern::new(item).is_ok_and(|p| p.matches(&action.command))),
+        });
+        if matched.is_empty() {
+            unmatched.push(item.to_string());
+        }
+        for i in matched {
+            removed[i] = true;
         }
     }
 
-    match fs::File::create(stalker_instance.join("stalklist.txt")) {
-        Ok(mut file) => {
-            for path in stalklist_item {
-                match writeln!(file, "{}", path) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        match execute!(
-                            stdout(),
-                            SetForegroundColor(Color::Red),
-                            Print(format!("Error writing path(s) to stalklist at {}: {}\n", stalker_instance.display(), e)),
-                            ResetColor
-                            ) {
-                            Ok(_) => {},
-                            Err(e) => eprintln!("Error printing stalklist line rewrite error output on remove_from_stalklist function: {}", e)
-                        }
-                    }
-                }
-            }
-        }
-        Err(e) => {
-            match execute!(
-                stdout(),
-                SetForegroundColor(Color::Red),
-                Print(format!("Error re-writing stalklist at {}: {}\n", stalker_instance.display(), e)),
-                ResetColor
-                ) {
-                Ok(_) => {}
-                Err(e) => eprintln!("Error printing stalklist file rewrite error output on remove_from_stalklist function: {}", e)
-            }
-        }
+    let (gone, remaining) = partition(action_item, &removed);
+    if !gone.is_empty() {
+        write_actionlist(stalker_instance, &remaining)?;
     }
+    Ok(Removed { removed: gone, unmatched })
 }
 
-pub fn create_commands(stalker_instance: &Path) {
-    match fs::File::create(stalker_instance.join("actionlist.txt")) {
-        Ok(_) => {
-            match execute!(
-                stdout(),
-                SetForegroundColor(Color::Green),
-                Print("Successfully created actionlist\n"),
-                ResetColor
-                ) {
-               Ok(_) => {},
-               Err(e) => eprintln!("Error printing actionlist creation error output on create_commands function: {}", e)
-            }
-        }
-        Err(e) => {
-            match execute!(
-                stdout(),
-                SetForegroundColor(Color::Red),
-                Print(format!("Error creating actionlist at {}: {}\n", stalker_instance.display(), e)),
-                ResetColor
-                ) {
-               Ok(_) => {},
-               Err(e) => eprintln!("Error printing failed actionlist creation error output on create_commands function: {}", e)
-            }
-        }
-    }
+/// Watches the stalklist paths of `stalker_instance` and runs its actions on
+/// every change, until watching fails. Everything worth telling the user
+/// along the way is passed to `on_notice`.
+pub fn run_stalker(stalker_instance: &Path, on_notice: impl FnMut(Notice) + Send + 'static) -> Result<(), StalkerError> {
+    StalkerBuilder::from_instance(stalker_instance)?
+        .on_notice(on_notice)
+        .build()?
+        .run()
 }
 
-pub fn update_commands(stalker_instance: &Path, command: &String) {
-    match OpenOptions::new()
-        .append(true)
-        .open(stalker_instance.join("actionlist.txt"))
-    {
-        Ok(mut file) => match writeln!(file, "{}", command) {
-            Ok(_) => match execute!(
-                stdout(),
-                SetForegroundColor(Color::Green),
-                Print(format!("Successfully added {} to actionlist.\n", command)),
-                ResetColor,
-            ) {
-                Ok(_) => {}
-                Err(e) => eprintln!(
-                    "Error printing actionlist update output on update_commands function: {}",
-                    e
-                ),
-            },
-            Err(e) => match execute!(
-                stdout(),
-                SetForegroundColor(Color::Red),
-                Print(format!("Error adding {} to actionlist: {}\n", command, e)),
-                ResetColor
-            ) {
-                Ok(_) => {}
-                Err(e) => eprintln!(
-                    "Error printing actionlist update error output on update_commands function: {}",
-                    e
-                ),
-            },
-        },
-        Err(e) => {
-            match execute!(
-                    stdout(),
-                    SetForegroundColor(Color::Red),
-                    Print(format!("Error opening actionlist at {}: {}\n", stalker_instance.display(), e)),
-                    ResetColor
-                    ) {
-                   Ok(_) => {},
-                   Err(e) => eprintln!("Error printing actionlist opening error output on update_commands function: {}",e)
-                }
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An instance whose stalklist and actionlist hold `paths` and `commands`,
+    // the actions numbered from 1.
+    fn instance(paths: &[&str], commands: &[&str]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let entries: Vec<WatchEntry> = paths.iter().map(|path| WatchEntry::new(*path)).collect();
+        write_stalklist(dir.path(), &entries).unwrap();
+        let actions: Vec<ActionEntry> = (1..)
+            .zip(commands)
+            .map(|(id, command)| ActionEntry {
+                id: Some(id),
+                ..ActionEntry::new(*command)
+            })
+            .collect();
+        write_actionlist(dir.path(), &actions).unwrap();
+        dir
     }
-}
 
-pub fn remove_from_actionlist(stalker_instance: &Path, action_to_remove: Vec<&String>) {
-    let mut action_item: Vec<String> = Vec::new();
-
-    match fs::File::open(stalker_instance.join("actionlist.txt")) {
-        Ok(file) => {
-            for line in BufReader::new(file).lines() {
-                match line {
-                    Ok(item) => action_item.push(item),
-                    Err(e) => {
-                        match execute!(
-                            stdout(),
-                            SetForegroundColor(Color::Red),
-                            Print(format!("Error reading line(s): {}\n", e)),
-                            ResetColor
-                            ) {
-                            Ok(_) => {},
-                            Err(e) => eprintln!("Error printing actionlist item removal error output on remove_from_actionlist function: {}", e)
-                        }
-                    }
-                }
-            }
-        }
-        Err(e) => {
-            match execute!(
-                stdout(),
-                SetForegroundColor(Color::Red),
-                Print(format!("Error opening actionlist at {}: {}\n", stalker_instance.join("actionlist.txt").display(), e)),
-                ResetColor
-                ) {
-                Ok(_) => {},
-                Err(e) => eprintln!("Error printing actionlist opening error output on remove_from_ actionlist function: {}", e)
-            }
-        }
+    fn listed(dir: &tempfile::TempDir) -> Vec<PathBuf> {
+        read_stalklist(dir.path()).unwrap().into_iter().map(|entry| entry.path).collect()
     }
 
-    if !stalker_instance.join("actionlist.txt").exists() {
-        match execute!(
-            stdout(),
-            SetForegroundColor(Color::Red),
-            Print(format!("Error opening actionlist at {}: Cannot find actionlist.txt\n", stalker_instance.join("actionlist.txt").display())),
-            ResetColor
-            ) {
-            Ok(_) => {},
-            Err(e) => eprintln!("Error printing actionlist existence checking error output on remove_from_actionlist function: {}", e)
-        }
-        return;
-    } else if action_item.is_empty() {
-        match execute!(
-            stdout(),
-            SetForegroundColor(Color::Red),
-            Print(format!("Error deleting item from actionlist at {}: actionlist.txt is empty\n", stalker_instance.join("actionlist.txt").display())),
-            ResetColor
-            ) {
-            Ok(_) => {},
-            Err(e) => eprintln!("Error printing actionlist empty checking error output on remove_from_actionlist function: {}", e)
-        }
-        return;
+    fn removed_paths(removed: &Removed<WatchEntry>) -> Vec<&Path> {
+        removed.removed.iter().map(|entry| entry.path.as_path()).collect()
     }
 
-    for item in action_to_remove {
-        action_item.retain(|i| i != item);
-        match execute!(
-            stdout(),
-            SetForegroundColor(Color::Green),
-            Print(format!("Successfully removed {} from actionlist\n", item)),
-            ResetColor
-        ) {
-            Ok(_) => {}
-            Err(e) => eprintln!(
-                "Error printing remove actionlist item output on remove_from_actionlist function: {}",
-                e
-            ),
-        }
+    #[test]
+    fn stalklist_entries_are_removed_by_index_path_or_glob() {
+        let dir = instance(&["/w/a.txt", "/w/b.rs", "/w/c.rs", "/w/d.txt"], &[]);
+
+        let removed = remove_from_stalklist(dir.path(), &["1", "/w/*.rs"]).unwrap();
+        assert_eq!(removed_paths(&removed), [Path::new("/w/a.txt"), Path::new("/w/b.rs"), Path::new("/w/c.rs")]);
+        assert!(removed.unmatched.is_empty());
+        assert_eq!(listed(&dir), [PathBuf::from("/w/d.txt")]);
+
+        let removed = remove_from_stalklist(dir.path(), &["/w/d.txt"]).unwrap();
+        assert_eq!(removed_paths(&removed), [Path::new("/w/d.txt")]);
+        assert!(listed(&dir).is_empty());
     }
 
-    match fs::File::create(stalker_instance.join("actionlist.txt")) {
-        Ok(mut file) => {
-            for path in action_item{
-                match writeln!(file, "{}", path) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        match execute!(
-                            stdout(),
-                            SetForegroundColor(Color::Red),
-                            Print(format!("Error writing path(s) to actionlist at {}: {}\n", stalker_instance.display(), e)),
-                            ResetColor
-                            ) {
-                            Ok(_) => {},
-                            Err(e) => eprintln!("Error printing actionlist line rewrite error output on remove_from_actionlist function: {}", e)
-                        }
-                    }
-                }
-            }
-        }
-        Err(e) => {
-            match execute!(
-                stdout(),
-                SetForegroundColor(Color::Red),
-                Print(format!("Error re-writing actionlist at {}: {}\n", stalker_instance.display(), e)),
-                ResetColor
-                ) {
-                Ok(_) => {}
-                Err(e) => eprintln!("Error printing actionlist file rewrite error output on remove_from_actionlist function: {}", e)
-            }
-        }
+    #[test]
+    fn indexes_out_of_range_and_globs_matching_nothing_are_unmatched() {
+        let dir = instance(&["/w/a.txt", "/w/b.txt"], &[]);
+
+        let removed = remove_from_stalklist(dir.path(), &["0", "3", "/elsewhere/*", "/w/b.txt"]).unwrap();
+        assert_eq!(removed_paths(&removed), [Path::new("/w/b.txt")]);
+        assert_eq!(removed.unmatched, ["0", "3", "/elsewhere/*"]);
+
+        let removed = remove_from_stalklist(dir.path(), &["/w/*.rs"]).unwrap();
+        assert!(removed.removed.is_empty());
+        assert_eq!(removed.unmatched, ["/w/*.rs"]);
+        assert_eq!(listed(&dir), [PathBuf::from("/w/a.txt")]);
     }
-}
 
+    #[test]
+    fn an_exact_path_with_glob_characters_only_removes_itself() {
+        let dir = instance(&["/w/[ab].txt", "/w/a.txt"], &[]);
+        let removed = remove_from_stalklist(dir.path(), &["/w/[ab].txt"]).unwrap();
+        assert_eq!(removed_paths(&removed), [Path::new("/w/[ab].txt")]);
+        assert_eq!(listed(&dir), [PathBuf::from("/w/a.txt")]);
+    }
 
-pub fn run_stalker(stalker_instance: &Path) {
-    let mut path_vec: Vec<PathBuf> = Vec::new();
-    let mut command_vec: Vec<String> = Vec::new();
-
-       match File::open(stalker_instance.join("stalklist.txt")) {
-        Ok(file) => {
-            for paths in BufReader::new(file).lines() {
-                match paths {
-                    Ok(path) => {
-                        for entry in WalkDir::new(&path) {
-                            match entry {
-                                Ok(true_path) => path_vec.push(true_path.path().to_owned()),
-                                Err(e) => {
-                                    match execute!(
-                                        stdout(),
-                                        SetForegroundColor(Color::Red),
-                                        Print(format!("Error getting true path {}: {}\n", path, e)),
-                                        ResetColor
-                                        ) {
-                                       Ok(_) => {},
-                                       Err(e) => {
-                                           eprintln!("Error printing true path error output on run_stalker function: {}", e);
-                                           return ;
-                                       }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        match execute!(
-                            stdout(),
-                            SetForegroundColor(Color::Red),
-                            Print(format!("Error reading stalklist at {}: {}\n", stalker_instance.display(), e)),
-                            ResetColor
-                            ) {
-                           Ok(_) => {},
-                           Err(e) => {
-                               eprintln!("Error printing stalklist reading error output on run_stalker function: {}", e);
-                               return ;
-                           }
-                        }
-                    }
-                }
-            }
-        }
-        Err(e) => {
-            match execute!(
-                stdout(),
-                SetForegroundColor(Color::Red),
-                Print(format!(
-                    "Error opening stalklist at {}: {}\n",
-                    stalker_instance.display(),
-                    e
-                )),
-                ResetColor
-            ) {
-                Ok(_) => {}
-                Err(e) => {
-                    eprintln!(
-                    "Error printing stalklist opening error output at run_stalker function: {}",
-                    e
-                );
-                    return ;
-                }
-            }
-        }
+    #[test]
+    fn removing_from_an_empty_or_missing_list_is_an_error() {
+        let dir = instance(&[], &[]);
+        assert!(matches!(remove_from_stalklist(dir.path(), &["1"]), Err(StalkerError::NotFound(_))));
+        assert!(matches!(remove_from_actionlist(dir.path(), &["1"]), Err(StalkerError::NotFound(_))));
+
+        let missing = dir.path().join("missing");
+        assert!(matches!(remove_from_stalklist(&missing, &["1"]), Err(StalkerError::NoInstance(_))));
     }
 
-    match File::open(stalker_instance.join("actionlist.txt")) {
-        Ok(file) => {
-            for actions in BufReader::new(file).lines() {
-                match actions {
-                    Ok(action) => command_vec.push(action),
-                    Err(e) => {
-                        match execute!(
-                            stdout(),
-                            SetForegroundColor(Color::Red),
-                            Print(format!("Error reading actionlist at {}: {}\n", stalker_instance.display(), e)),
-                            ResetColor
-                            ) {
-                           Ok(_) => {},
-                           Err(e) => {
-                               eprintln!("Error printing actionlist reading error output at run_stalker function: {}", e);
-                               return ;
-                           }
-                        }
-                    }
-                }
-            }
-        }
-        Err(e) => {
-            match execute!(
-                stdout(),
-                SetForegroundColor(Color::Red),
-                Print(format!(
-                    "Error opening actionlist at {}: {}\n",
-                    stalker_instance.display(),
-                    e
-                )),
-                ResetColor
-            ) {
-                Ok(_) => {}
-                Err(e) => {
-                    eprintln!(
-                    "Error printing actionlist opening error output at run_stalker function: {}",
-                    e
-                );
-                    return ;
-                }
-            }
-        }
+    #[test]
+    fn actions_are_removed_by_id_command_or_glob() {
+        let dir = instance(&[], &["cargo build", "cargo test *", "make", "echo {path}"]);
+
+        // "cargo test *" is taken as the exact command, not as a glob.
+        let removed = remove_from_actionlist(dir.path(), &["3", "cargo test *", "9", "npm *"]).unwrap();
+        let commands: Vec<&str> = removed.removed.iter().map(|action| action.command.as_str()).collect();
+        assert_eq!(commands, ["cargo test *", "make"]);
+        assert_eq!(removed.unmatched, ["9", "npm *"]);
+
+        let removed = remove_from_actionlist(dir.path(), &["*o*"]).unwrap();
+        let ids: Vec<Option<u32>> = removed.removed.iter().map(|action| action.id).collect();
+        assert_eq!(ids, [Some(1), Some(4)]);
+        assert!(read_actionlist(dir.path()).unwrap().is_empty());
     }
 
-    for path in path_vec {
-        let (tx, rx) = channel();
-        let mut watcher =
-            watcher(tx, Duration::from_secs(5)).expect("Error creating watcher object");
-        watcher
-            .watch(&path, RecursiveMode::Recursive)
-            .expect("Error watching file");
-        loop {
-            match rx.recv() {
-                Ok(event) => {
-                    if event == DebouncedEvent::NoticeWrite(path.to_path_buf()) {
-                        for raw_command in &command_vec {
-                            let command_replaced = str::replace(
-                                raw_command,
-                                "{path}",
-                                path.to_str().expect("Error substituting command"),
-                            );
-                            let mut actual_command = command(&command_replaced);
-                            actual_command.stdout(Stdio::piped());
-                            let output = actual_command.execute_output().unwrap();
-                            println!("{}", String::from_utf8(output.stdout).unwrap());
-                        }
-                    }
-                }
-                Err(e) => {
-                    match execute!(
-                        stdout(),
-                        SetForegroundColor(Color::Red),
-                        Print(format!("Error receiving event: {}\n", e)),
-                        ResetColor
-                        ) {
-                        Ok(_) => {},
-                        Err(e) => {
-                            eprintln!("Error printing event receiving error output on run_stalker function: {}", e);
-                            return ;
-                        }
-                    }
-                }
-            }
-        }
-    } 
+    #[test]
+    fn partition_keeps_the_order_of_both_sides() {
+        let (gone, remaining) = partition(vec!['a', 'b', 'c', 'd'], &[false, true, false, true]);
+        assert_eq!(gone, ['b', 'd']);
+        assert_eq!(remaining, ['a', 'c']);
+    }
 }
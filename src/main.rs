@@ -1,12 +1,25 @@
-use clap::{arg, Command};
-use crossterm::execute;
-use crossterm::style::{Color, Print, ResetColor, SetForegroundColor};
+use clap::{arg, Arg, Command};
 use dirs::home_dir;
+use serde_json::json;
+use stalker::action::{ActionEntry, DEFAULT_SHELL};
+use stalker::entry::{SymlinkReport, WatchEntry};
+use stalker::daemon::{self, DaemonStatus};
+use stalker::error::StalkerError;
+use stalker::notice::Notice;
+use stalker::paths;
+use stalker::report::{self, ColorChoice, Level, OutputFormat, Reporter};
 use stalker::{
-    create_commands, create_stalk_list, create_stalker_dir, list_action_list, list_stalk_list,
-    remove_from_stalklist, remove_from_actionlist, run_stalker, update_commands, update_stalk_list,
+    create_commands, create_stalk_list, create_stalker_dir, edit_action, list_action_list,
+    list_stalk_list, move_action, remove_from_stalklist, remove_from_actionlist,
+    update_commands, update_stalk_list, ControlServer, Controller, PauseMode, PollSource,
+    Removed, StalkerBuilder, StalklistUpdate,
 };
-use std::io::stdout;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use terminal_size::{terminal_size, Width};
 
 fn main() {
@@ -19,6 +32,20 @@ fn main() {
         .author("Peter <peterajaaa@gmail.com>")
         .about("stalker is a fast and simple file watcher and executor with git-like syntax.")
         .arg_required_else_help(true)
+        .arg(
+            arg!(--color <WHEN> "Color the output: auto (only on a terminal, and not when NO_COLOR is set), always or never")
+                .required(false)
+                .global(true)
+                .possible_values(["auto", "always", "never"])
+                .default_value("auto"),
+        )
+        .arg(
+            arg!(--output <FORMAT> "Print messages as text, as JSON lines, or quietly (errors only, on stderr)")
+                .required(false)
+                .global(true)
+                .possible_values(["text", "json", "quiet"])
+                .default_value("text"),
+        )
         .subcommand(
             Command::new("init")
                 .about("Initialize a stalker instance.
@@ -33,19 +60,33 @@ The stalker instance will be made on $HOME directory under '.stalker' folder.")
                         .required(true)
                         .takes_value(true)
                         .multiple_values(true),
-                ),
+                )
+                .arg(arg!(--"follow-symlinks" "Follow symlinked directories and files under the path(s), skipping symlink cycles"))
+                .arg(
+                    arg!(--report <MODE> "Report changes under a followed symlink against the link path or the resolved target")
+                        .required(false)
+                        .possible_values(["link", "target"])
+                        .default_value("link")
+                        .requires("follow-symlinks"),
+                )
+                .arg(
+                    arg!(--"max-depth" <N> "Only watch this many levels below the path(s); 1 watches just the top level of a directory")
+                        .required(false)
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(arg!(--"no-hidden" "Skip hidden files and directories (names starting with a dot) under the path(s)")),
         )
         .subcommand(
-            Command::new("list")
-            .about("Get all path(s) in the stalk-list")
+            list_format_args(Command::new("list")
+            .about("Get all path(s) in the stalk-list"))
             )
         .subcommand(
-            Command::new("list-action")
-            .about("Get all action(s) in the action-list")
+            list_format_args(Command::new("list-action")
+            .about("Get all action(s) in the action-list"))
             )
         .subcommand(
             Command::new("remove")
-                .about("Remove path(s) from the stalk-list. Put the path to remove inside of quotes (\"\"). To remove multiple paths, use space (\" \") to separate each path.")
+                .about("Remove path(s) from the stalk-list by index (as shown by 'stalk list'), by path, or by glob pattern (e.g. '/home/me/proj/*'). Put the path to remove inside of quotes (\"\"). To remove multiple paths, use space (\" \") to separate each path. Exits with an error if nothing matched.")
                 .arg_required_else_help(true)
                 .arg(
                     arg!([PATH])
@@ -56,7 +97,7 @@ The stalker instance will be made on $HOME directory under '.stalker' folder.")
         )
         .subcommand(
             Command::new("remove-action")
-            .about("Remove actions(s) from the action-list. Put the command to remove inside of quotes (\"\"). To remove multiple commands, use space (\" \") to separate each command.")
+            .about("Remove actions(s) from the action-list by ID (as shown by 'stalk list-action'), by command, or by glob pattern over commands. Put the command to remove inside of quotes (\"\"). To remove multiple commands, use space (\" \") to separate each command.")
             .arg_required_else_help(true)
             .arg(
                 arg!([ACTION])
@@ -65,36 +106,127 @@ The stalker instance will be made on $HOME directory under '.stalker' folder.")
                 .multiple_values(true),
                 ),
         )
+        .subcommand(
+            Command::new("edit-action")
+            .about("Change the command of an action, keeping its options. Without a new command, the current one is opened in $VISUAL or $EDITOR.")
+            .arg_required_else_help(true)
+            .arg(arg!(<ID> "ID of the action, as shown by 'stalk list-action'").value_parser(clap::value_parser!(u32)))
+            .arg(arg!([COMMAND] "The new command")),
+        )
+        .subcommand(
+            Command::new("move-action")
+            .about("Move an action to another position in the action-list. Actions run in action-list order.")
+            .arg_required_else_help(true)
+            .arg(arg!(<ID> "ID of the action, as shown by 'stalk list-action'").value_parser(clap::value_parser!(u32)))
+            .arg(arg!(<POSITION> "New position, starting at 1").value_parser(parse_position)),
+        )
         .subcommand(
             Command::new("do")
             .about("Specify operation(s) on item(s) in the stalk-list. Put the commands inside of quotes (\"\").
-Each separate command should be placed inside of separate quotes (e.g. \"git add *\" \"git commit\"). To insert path that's listed in the stalklist, use {path} as the placeholder (e.g. \"git add {path}\").")
+Each separate command should be placed inside of separate quotes (e.g. \"git add *\" \"git commit\"). To insert path that's listed in the stalklist, use {path} as the placeholder (e.g. \"git add {path}\"). {dir}, {event} and {root} insert the directory the path is in, the kind of change and the stalklist entry the path belongs to. All four are also exported to the command as STALKER_PATH, STALKER_DIR, STALKER_EVENT and STALKER_ROOT, along with STALKER_PATHS, which lists every file the command runs for when it runs once for several changes.")
             .arg_required_else_help(true)
             .arg(
                 arg!([COMMANDS])
                 .required(true)
                 .takes_value(true)
                 .multiple_values(true),
+                )
+            .arg(
+                Arg::new("shell")
+                .long("shell")
+                .help("Run the command(s) through a shell so pipes, &&, redirects and globs work (e.g. --shell, --shell=\"bash -lc\"). Placeholders are passed to the shell as quoted variable references, so don't put them inside quotes yourself.")
+                .value_name("SHELL")
+                .takes_value(true)
+                .min_values(0)
+                .require_equals(true)
+                .default_missing_value(DEFAULT_SHELL),
+                )
+            .arg(
+                arg!(--cwd <DIR> "Run the command(s) in this directory. Placeholders work here too, e.g. --cwd {dir} or --cwd {root}.")
+                .required(false),
+                )
+            .arg(
+                arg!(--env <VAR> "Set an environment variable for the command(s), as KEY=VALUE. Can be given more than once.")
+                .required(false)
+                .multiple_occurrences(true)
+                .validator(|var| match var.split_once('=') {
+                    Some((key, _)) if !key.is_empty() => Ok(()),
+                    _ => Err("expected KEY=VALUE"),
+                }),
+                )
+            .arg(arg!(--"clear-env" "Don't pass stalker's own environment to the command(s)"))
+            .arg(
+                arg!(--at <POSITION> "Insert the command(s) at this position in the action-list instead of at the end, starting at 1")
+                .required(false)
+                .value_parser(parse_position),
                 ),
                 )
         .subcommand(
             Command::new("execute")
             .about("Execute commands specified by 'stalk do' on path(s) specified by 'stalk-add'. Commands will be executed whenever there's changes on the specified file(s) on the specified path(s).")
+            .arg(
+                arg!(--poll <SECONDS> "Check the path(s) for changes every SECONDS instead of relying on the operating system's file events, e.g. on network filesystems")
+                .required(false)
+                .value_parser(clap::value_parser!(u64).range(1..)),
+                )
+            .arg(
+                arg!(--events <FORMAT> "Print what happens as text, or as one JSON object per line: each change received, and each action started and finished with its exit code, duration and output")
+                .required(false)
+                .possible_values(["text", "jsonl"])
+                .default_value("text"),
+                )
+            .arg(arg!(--daemon "Run in the background, detached from the terminal. The pid is written to stalker.pid and the output to stalker.log in the stalker instance."))
+            .arg(Arg::new("detached").long("detached").hide(true))
+            )
+        .subcommand(
+            list_format_args(Command::new("status")
+            .about("Show whether stalker is running in the background, and the path(s) and action(s) it watches with. Exits with 1 if it isn't running."))
+            )
+        .subcommand(pause_command())
+        .subcommand(resume_command())
+        .subcommand(
+            Command::new("ctl")
+            .about("Control the running stalker through the stalker.sock control socket in the stalker instance (Unix only).")
+            .subcommand_required(true)
+            .arg_required_else_help(true)
+            .subcommand(pause_command())
+            .subcommand(resume_command())
+            .subcommand(
+                Command::new("trigger")
+                .about("Run the action(s) now, even while paused, as if PATH had changed, or every path in the stalk-list without PATH. {event} is 'trigger'.")
+                .arg(arg!([PATH] "A watched path")),
+                )
+            .subcommand(Command::new("children").about("List the actions running right now, with the pid of the process each started"))
+            .subcommand(Command::new("reload").about("Read config.txt, the stalk-list and the action-list again and apply them"))
+            .subcommand(Command::new("shutdown").about("Stop the stalker once the action(s) it is running have finished"))
+            )
+        .subcommand(
+            Command::new("stop")
+            .about("Stop stalker running in the background, letting the action(s) it is running finish first.")
             )
         .get_matches();
 
-    match app.subcommand() {
+    let output = match app.get_one::<String>("output").map(String::as_str) {
+        Some("json") => OutputFormat::Json,
+        Some("quiet") => OutputFormat::Quiet,
+        _ => OutputFormat::Text,
+    };
+    let color = match app.get_one::<String>("color").map(String::as_str) {
+        Some("always") => ColorChoice::Always,
+        Some("never") => ColorChoice::Never,
+        _ => ColorChoice::Auto,
+    };
+    let mut reporter = report::reporter(output, color);
+    let reporter = reporter.as_mut();
+
+    let status = match app.subcommand() {
         Some(("init", _init_path)) => {
-            match execute!(
-                stdout(),
-                SetForegroundColor(Color::Green),
-                Print("stalker initialized\n"),
-                ResetColor
-                ) {
-                Ok(_) => {}
-                Err(_) => eprintln!("Error printing stalker init message"),
+            reporter.report(Level::Success, "stalker initialized");
+            let created = create_stalker_dir(&default_stalker_path);
+            if created.is_ok() {
+                reporter.report(Level::Success, &format!("Successfully created stalker instance at {}", default_stalker_path.display()));
             }
-            create_stalker_dir(&default_stalker_path);
+            exit_status(reporter, created)
         }
         Some(("add", add_path)) => {
             /* Used Vec<&String> instead of Vec<_> to better show the data types within the vector.
@@ -103,88 +235,803 @@ Each separate command should be placed inside of separate quotes (e.g. \"git add
             /* Also a Vec<&String> is used because get_many() returns a reference to the actual
              * value.*/
             let paths: Vec<&String> = add_path.get_many::<String>("PATH").unwrap().collect();
-            if !default_stalker_path.exists() {
-                match execute!(
-                    stdout(),
-                    SetForegroundColor(Color::Red),
-                    Print("Error creating stalklist. No stalker instance is found."),
-                    ResetColor
-                    ) {
-                    Ok(_) => {}
-                    Err(_) => eprintln!("Error printing stalker add error message"),
-                }
-                match execute!(
-                    stdout(),
-                    SetForegroundColor(Color::Yellow),
-                    Print(
-                        "HINT: Run \"stalker init\" first before adding item(s) to the stalklist."
-                        ),
-                        ResetColor
-                        ) {
-                    Ok(_) => {}
-                    Err(_) => eprintln!("Error printing stalker add hint message"),
-                }
-            } else if default_stalker_path.join("stalklist.txt").exists() {
-                for path in paths {
-                    update_stalk_list(&default_stalker_path, path)
-                }
+            let entries: Vec<WatchEntry> = paths
+                .into_iter()
+                .map(|path| WatchEntry {
+                    follow_symlinks: add_path.contains_id("follow-symlinks"),
+                    report: match add_path.get_one::<String>("report").map(String::as_str) {
+                        Some("target") => SymlinkReport::Target,
+                        _ => SymlinkReport::Link,
+                    },
+                    max_depth: add_path.get_one::<usize>("max-depth").copied(),
+                    no_hidden: add_path.contains_id("no-hidden"),
+                    ..WatchEntry::new(path)
+                })
+                .collect();
+            let created = if default_stalker_path.join("stalklist.txt").exists() {
+                Ok(())
             } else {
-                create_stalk_list(&default_stalker_path);
-                for path in paths {
-                    update_stalk_list(&default_stalker_path, path);
+                create_stalk_list(&default_stalker_path)
+                    .map(|_| reporter.report(Level::Success, "Successfully created stalklist"))
+            };
+            match created {
+                Ok(_) => {
+                    let mut status = 0;
+                    for entry in &entries {
+                        let updated = update_stalk_list(&default_stalker_path, entry).map(|update| report_update(reporter, update));
+                        status = first_failure(status, exit_status(reporter, updated));
+                    }
+                    status
                 }
+                Err(e) => exit_status(reporter, Err(e)),
             }
         }
-        Some(("list", _list_subcommand)) => list_stalk_list(&default_stalker_path),
-        Some(("list-action", _list_action_subcommand)) => list_action_list(&default_stalker_path),
+        Some(("list", list_args)) => {
+            let listed = list_stalk_list(&default_stalker_path)
+                .map(|entries| print_stalklist(reporter, list_format(list_args), &entries));
+            exit_status(reporter, listed)
+        }
+        Some(("list-action", list_args)) => {
+            let listed = list_action_list(&default_stalker_path)
+                .map(|actions| print_actionlist(reporter, list_format(list_args), &actions));
+            exit_status(reporter, listed)
+        }
         Some(("remove", remove_path)) => {
-            let paths: Vec<&String> = remove_path.get_many::<String>("PATH").unwrap().collect();
-            remove_from_stalklist(&default_stalker_path, paths)
+            let paths: Vec<&str> = remove_path.get_many::<String>("PATH").unwrap().map(String::as_str).collect();
+            removal_status(
+                reporter,
+                remove_from_stalklist(&default_stalker_path, &paths),
+                "stalklist",
+                |entry| entry.path.display().to_string(),
+            )
         }
         Some(("remove-action", remove_action)) => {
-            let actions: Vec<&String> = remove_action.get_many::<String>("ACTION").unwrap().collect();
-            remove_from_actionlist(&default_stalker_path, actions);
+            let actions: Vec<&str> = remove_action.get_many::<String>("ACTION").unwrap().map(String::as_str).collect();
+            removal_status(
+                reporter,
+                remove_from_actionlist(&default_stalker_path, &actions),
+                "actionlist",
+                |action| action.command.clone(),
+            )
         }
 
         Some(("do", user_commands)) => {
-            let commands: Vec<&String> = user_commands
+            let commands: Vec<ActionEntry> = user_commands
                 .get_many::<String>("COMMANDS")
                 .unwrap()
+                .map(|command| ActionEntry {
+                    shell: user_commands.get_one::<String>("shell").cloned(),
+                    cwd: user_commands.get_one::<String>("cwd").cloned(),
+                    env: user_commands
+                        .get_many::<String>("env")
+                        .unwrap_or_default()
+                        .filter_map(|var| var.split_once('='))
+                        .map(|(key, value)| (key.to_string(), value.to_string()))
+                        .collect(),
+                    clear_env: user_commands.contains_id("clear-env"),
+                    ..ActionEntry::new(command)
+                })
                 .collect();
 
-            if !default_stalker_path.exists() {
-                match execute!(
-                    stdout(),
-                    SetForegroundColor(Color::Red),
-                    Print("Error creating actionlist. No stalker instance is found."),
-                    ResetColor
-                    ) {
-                    Ok(_) => {}
-                    Err(_) => eprintln!("Error printing stalker do error message"),
-                }
-                match execute!(
-                    stdout(),
-                    SetForegroundColor(Color::Yellow),
-                    Print("HINT: Run \"stalker init\" first before adding command(s) to the actionlist."),
-                    ResetColor
-                    ) {
-                    Ok(_) => {},
-                    Err(_) => eprintln!("Error printing stalker do hint message")
+            let created = if default_stalker_path.join("actionlist.txt").exists() {
+                Ok(())
+            } else {
+                create_commands(&default_stalker_path)
+                    .map(|_| reporter.report(Level::Success, "Successfully created actionlist"))
+            };
+            let at = user_commands.get_one::<usize>("at");
+            match created {
+                Ok(_) => {
+                    let mut status = 0;
+                    for (i, command) in commands.iter().enumerate() {
+                        let added = update_commands(&default_stalker_path, command, at.map(|p| p + i)).map(|action| {
+                            reporter.report(Level::Success, &format!("Successfully added {} to actionlist as action {}.", action, action.id.unwrap_or_default()))
+                        });
+                        status = first_failure(status, exit_status(reporter, added));
+                    }
+                    status
                 }
-            } else if default_stalker_path.join("actionlist.txt").exists() {
-                for command in commands {
-                    update_commands(&default_stalker_path, command)
+                Err(e) => exit_status(reporter, Err(e)),
+            }
+        }
+        Some(("edit-action", edit_args)) => {
+            let id = *edit_args.get_one::<u32>("ID").unwrap();
+            let command = edit_args.get_one::<String>("COMMAND").map(String::as_str);
+            let edited = edit_action(&default_stalker_path, id, command).map(|edited| match edited {
+                Some(action) => reporter.report(Level::Success, &format!("Successfully changed action {} to {}", id, action)),
+                None => reporter.report(Level::Warning, &format!("Action {} left unchanged", id)),
+            });
+            exit_status(reporter, edited)
+        }
+        Some(("move-action", move_args)) => {
+            let id = *move_args.get_one::<u32>("ID").unwrap();
+            let position = *move_args.get_one::<usize>("POSITION").unwrap();
+            let moved = move_action(&default_stalker_path, id, position).map(|position| {
+                reporter.report(Level::Success, &format!("Successfully moved action {} to position {}", id, position))
+            });
+            exit_status(reporter, moved)
+        }
+        Some(("execute", execute_args)) if execute_args.contains_id("daemon") => {
+            // Validate the instance here, where errors can still be seen.
+            let started = StalkerBuilder::from_instance(&default_stalker_path)
+                .and_then(StalkerBuilder::build)
+                .and_then(|_| daemon::start(&default_stalker_path, &daemon_args()))
+                .map(|pid| {
+                    reporter.report(Level::Success, &format!(
+                        "stalker is running in the background (pid {}), logging to {}",
+                        pid,
+                        daemon::log_file(&default_stalker_path).display()
+                    ))
+                });
+            exit_status(reporter, started)
+        }
+        Some(("execute", execute_args)) => {
+            let jsonl = execute_args.get_one::<String>("events").is_some_and(|events| events == "jsonl");
+            let mut events = EventReporter;
+            let reporter: &mut dyn Reporter = if jsonl { &mut events } else { reporter };
+            // The stalker reports from its own thread, so it gets a reporter of its own.
+            let mut notices = report::reporter(output, color);
+            let on_notice = move |notice| {
+                if jsonl {
+                    print_event(notice)
+                } else {
+                    report_notice(notices.as_mut(), notice)
                 }
+            };
+            // A background stalker claims the instance before anything else,
+            // so `stalk execute --daemon` returns only once its pidfile is there.
+            let claimed = if execute_args.contains_id("detached") {
+                daemon::claim(&default_stalker_path).map(Some)
             } else {
-                create_commands(&default_stalker_path);
-                for command in commands {
-                    update_commands(&default_stalker_path, command)
+                Ok(None)
+            };
+            let result = claimed.and_then(|pid_file| {
+                if pid_file.is_some() {
+                    reporter.report(Level::Success, &format!("stalker started (pid {})", process::id()));
                 }
+                let result = daemon::termination_flag().and_then(|terminate| {
+                    let mut builder = StalkerBuilder::from_instance(&default_stalker_path)?.on_notice(on_notice);
+                    if let Some(seconds) = execute_args.get_one::<u64>("poll") {
+                        builder = builder.event_source(PollSource::new(Duration::from_secs(*seconds)));
+                    }
+                    let stalker = builder.build()?;
+                    let _control = serve_control(reporter, &default_stalker_path, stalker.controller());
+                    stalker.run_until(&terminate)
+                });
+                if let Some(pid_file) = pid_file {
+                    drop(pid_file);
+                    reporter.report(Level::Success, &format!("stalker stopped (pid {})", process::id()));
+                }
+                result
+            });
+            exit_status(reporter, result)
+        }
+        Some(("status", status_args)) => match daemon::status(&default_stalker_path) {
+            Ok(status) => {
+                let listed = list_stalk_list(&default_stalker_path).and_then(|entries| {
+                    let actions = list_action_list(&default_stalker_path)?;
+                    print_status(reporter, list_format(status_args), &default_stalker_path, status, &entries, &actions);
+                    Ok(())
+                });
+                match (exit_status(reporter, listed), status) {
+                    (0, DaemonStatus::Running(_)) => 0,
+                    (0, _) => exit_code(&StalkerError::NotFound(String::new())),
+                    (failed, _) => failed,
+                }
+            }
+            Err(e) => exit_status(reporter, Err(e)),
+        },
+        Some(("ctl", ctl_args)) => {
+            let (command, command_args) = ctl_args.subcommand().expect("ctl requires a subcommand");
+            let sent = ctl_request(command, command_args)
+                .and_then(|request| send_control(&default_stalker_path, &request))
+                .map(|response| report_control(reporter, &response));
+            exit_status(reporter, sent)
+        }
+        Some((command @ ("pause" | "resume"), command_args)) => {
+            let sent = ctl_request(command, command_args)
+                .and_then(|request| send_control(&default_stalker_path, &request))
+                .map(|response| report_control(reporter, &response));
+            exit_status(reporter, sent)
+        }
+        Some(("stop", _)) => {
+            let stopped = daemon::stop(&default_stalker_path)
+                .map(|pid| reporter.report(Level::Success, &format!("Successfully stopped stalker (pid {})", pid)));
+            exit_status(reporter, stopped)
+        }
+        _ => 0, //Done because every subcommand should raise help on error.
+    };
+    process::exit(status);
+}
+
+/// How list-style subcommands print their records.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ListFormat {
+    /// `INDEX: entry (options)` lines.
+    Plain,
+    /// Aligned columns under a header.
+    Table,
+    /// A JSON array of objects, one per record.
+    Json,
+}
+
+// Actionlist positions start at 1.
+fn parse_position(value: &str) -> Result<usize, String> {
+    match value.parse() {
+        Ok(0) => Err(String::from("positions start at 1")),
+        Ok(position) => Ok(position),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// Shared by `stalk pause` and `stalk ctl pause`.
+fn pause_command() -> Command<'static> {
+    Command::new("pause")
+        .about("Stop running actions for changes in the running stalker until resumed, without stopping it")
+        .arg(
+            arg!(--mode <MODE> "What to do with changes made meanwhile: discard them, accumulate them to run the action(s) for each changed path on resume, or run the action(s) once on resume with every changed path in STALKER_PATHS. Defaults to the pause_mode config setting.")
+                .required(false)
+                .possible_values(["discard", "accumulate", "once"]),
+        )
+}
+
+fn resume_command() -> Command<'static> {
+    Command::new("resume").about("Run actions for changes again after 'stalk pause'")
+}
+
+fn list_format_args(command: Command) -> Command {
+    command
+        .arg(
+            arg!(--format <FORMAT> "How to print the list")
+                .required(false)
+                .possible_values(["plain", "table", "json"])
+                .default_value("plain"),
+        )
+        .arg(arg!(--json "Print the list as JSON, same as --format json").conflicts_with("format"))
+}
+
+fn list_format(args: &clap::ArgMatches) -> ListFormat {
+    if args.contains_id("json") {
+        return ListFormat::Json;
+    }
+    match args.get_one::<String>("format").map(String::as_str) {
+        Some("table") => ListFormat::Table,
+        Some("json") => ListFormat::Json,
+        _ => ListFormat::Plain,
+    }
+}
+
+fn print_stalklist(reporter: &mut dyn Reporter, format: ListFormat, entries: &[WatchEntry]) {
+    let exists = |entry: &WatchEntry| fs::symlink_metadata(&entry.path).is_ok();
+    match format {
+        ListFormat::Plain => {
+            for (index, entry) in entries.iter().enumerate() {
+                reporter.report(Level::Item, &format!("{}: {}", index + 1, entry));
+            }
+        }
+        ListFormat::Table => print_table(
+            reporter,
+            ["INDEX", "PATH", "EXISTS", "OPTIONS"],
+            entries.iter().enumerate().map(|(index, entry)| {
+                [
+                    (index + 1).to_string(),
+                    entry.path.display().to_string(),
+                    String::from(if exists(entry) { "yes" } else { "no" }),
+                    entry.options().join(", "),
+                ]
+            }),
+        ),
+        ListFormat::Json => println!("{}", stalklist_json(entries)),
+    }
+}
+
+fn stalklist_json(entries: &[WatchEntry]) -> serde_json::Value {
+    entries
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            json!({
+                "index": index + 1,
+                "path": entry.path.to_string_lossy(),
+                "exists": fs::symlink_metadata(&entry.path).is_ok(),
+                "follow_symlinks": entry.follow_symlinks,
+                "report": match entry.report {
+                    SymlinkReport::Link => "link",
+                    SymlinkReport::Target => "target",
+                },
+                "max_depth": entry.max_depth,
+                "no_hidden": entry.no_hidden,
+            })
+        })
+        .collect()
+}
+
+fn print_actionlist(reporter: &mut dyn Reporter, format: ListFormat, actions: &[ActionEntry]) {
+    match format {
+        ListFormat::Plain => {
+            for action in actions {
+                reporter.report(Level::Item, &format!("{}: {}", action.id.unwrap_or_default(), action));
+            }
+        }
+        ListFormat::Table => print_table(
+            reporter,
+            ["ID", "COMMAND", "OPTIONS"],
+            actions.iter().map(|action| {
+                [
+                    action.id.unwrap_or_default().to_string(),
+                    action.command.clone(),
+                    action.options().join(", "),
+                ]
+            }),
+        ),
+        ListFormat::Json => println!("{}", actionlist_json(actions)),
+    }
+}
+
+fn actionlist_json(actions: &[ActionEntry]) -> serde_json::Value {
+    actions
+        .iter()
+        .enumerate()
+        .map(|(index, action)| {
+            let env: serde_json::Map<String, serde_json::Value> = action
+                .env
+                .iter()
+                .map(|(key, value)| (key.clone(), json!(value)))
+                .collect();
+            json!({
+                "id": action.id,
+                "position": index + 1,
+                "command": action.command,
+                "shell": action.shell,
+                "cwd": action.cwd,
+                "env": env,
+                "clear_env": action.clear_env,
+            })
+        })
+        .collect()
+}
+
+fn print_status(
+    reporter: &mut dyn Reporter,
+    format: ListFormat,
+    stalker_instance: &Path,
+    status: DaemonStatus,
+    entries: &[WatchEntry],
+    actions: &[ActionEntry],
+) {
+    let log = daemon::log_file(stalker_instance);
+    if format == ListFormat::Json {
+        let pid = match status {
+            DaemonStatus::Running(pid) => Some(pid),
+            DaemonStatus::Stale(_) | DaemonStatus::Stopped => None,
+        };
+        return println!(
+            "{}",
+            json!({
+                "running": pid.is_some(),
+                "pid": pid,
+                "log": log.to_string_lossy(),
+                "stalklist": stalklist_json(entries),
+                "actionlist": actionlist_json(actions),
+            })
+        );
+    }
+    match status {
+        DaemonStatus::Running(pid) => {
+            reporter.report(Level::Success, &format!("stalker is running in the background (pid {})", pid));
+            reporter.report(Level::Item, &format!("Log: {}", log.display()));
+        }
+        DaemonStatus::Stale(pid) => reporter.report(
+            Level::Warning,
+            &format!("stalker is not running (stalker pid {} has exited without removing its pidfile, see {})", pid, log.display()),
+        ),
+        DaemonStatus::Stopped => reporter.report(Level::Warning, "stalker is not running"),
+    }
+    reporter.report(Level::Item, "Watching:");
+    print_stalklist(reporter, format, entries);
+    reporter.report(Level::Item, "Actions:");
+    print_actionlist(reporter, format, actions);
+}
+
+fn print_table<const N: usize>(reporter: &mut dyn Reporter, header: [&str; N], rows: impl Iterator<Item = [String; N]>) {
+    let rows: Vec<[String; N]> = rows.collect();
+    let mut widths = header.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let line = |cells: [&str; N]| {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        padded.join("  ").trim_end().to_string()
+    };
+    reporter.report(Level::Item, &line(header));
+    for row in &rows {
+        reporter.report(Level::Item, &line(row.each_ref().map(String::as_str)));
+    }
+}
+
+fn report_update(reporter: &mut dyn Reporter, update: StalklistUpdate) {
+    match update {
+        StalklistUpdate::Added(entry) => {
+            reporter.report(Level::Success, &format!("Successfully added {} to stalklist", entry));
+            if fs::symlink_metadata(&entry.path).is_err() {
+                reporter.report(Level::Warning, &format!("Warning: {} does not exist yet. It will be watched once it is created.", entry.path.display()));
             }
         }
-        Some(("execute", _execute_subcommand)) => {
-            run_stalker(&default_stalker_path);
+        StalklistUpdate::AlreadyListed(entry) => {
+            reporter.report(Level::Warning, &format!("{} is already in the stalklist, skipping it", entry.path.display()))
         }
-        _ => (), //Done because every subcommand should raise help on error.
+    }
+}
+
+fn report_notice(reporter: &mut dyn Reporter, notice: Notice) {
+    if let Some((level, message)) = notice_message(notice) {
+        reporter.report(level, &message);
+    }
+}
+
+// What a notice says in text output, if anything.
+fn notice_message(notice: Notice) -> Option<(Level, String)> {
+    let message = match notice {
+        Notice::Appeared(path) => (Level::Success, format!("{} appeared, now watching it", path.display())),
+        Notice::Pending { path, ancestor } => (
+            Level::Warning,
+            format!("{} does not exist yet, waiting for it to appear (watching {})", path.display(), ancestor.display()),
+        ),
+        Notice::SymlinkCycle(path) => (Level::Warning, format!("Warning: skipping symlink cycle at {}", path.display())),
+        Notice::WatchFailed { path, error } => (Level::Error, format!("Error watching {}: {}", path.display(), error)),
+        Notice::EnvFileReloaded(path) => (Level::Success, format!("Reloaded env_file {}", path.display())),
+        Notice::EnvFileFailed { path, error } => (Level::Error, format!("Error reading env_file {}: {}", path.display(), error)),
+        Notice::LoopDetected { path, count, ignored_for } => (
+            Level::Warning,
+            format!(
+                "Warning: {} was triggered {} times in a row, possibly by its own action(s). Ignoring it for {}s.",
+                path.display(),
+                count,
+                ignored_for.as_secs()
+            ),
+        ),
+        Notice::Paused(mode) => (
+            Level::Warning,
+            String::from(match mode {
+                PauseMode::Discard => "Paused, ignoring changes until resumed",
+                PauseMode::Accumulate => "Paused, holding changes until resumed",
+                PauseMode::Once => "Paused, holding changes to run the action(s) once when resumed",
+            }),
+        ),
+        Notice::Resumed { mode, held } => (
+            Level::Success,
+            match mode {
+                _ if held == 0 => String::from("Resumed"),
+                PauseMode::Once => format!("Resumed, running the action(s) once for {} changed path(s)", held),
+                _ => format!("Resumed, running the action(s) for {} changed path(s)", held),
+            },
+        ),
+        Notice::ConfigReloaded(path) => (Level::Success, format!("Reloaded {}", path.display())),
+        Notice::Watching(entry) => (Level::Success, format!("Now watching {}", entry)),
+        Notice::Unwatched(path) => (Level::Success, format!("Stopped watching {}", path.display())),
+        Notice::ActionAdded { action, id } => (Level::Success, format!("Added action {}: {}", id.unwrap_or_default(), action)),
+        Notice::ActionRemoved { action, id } => (Level::Success, format!("Removed action {}: {}", id.unwrap_or_default(), action)),
+        // The error names the file already.
+        Notice::ReloadFailed { error, .. } => (Level::Error, format!("Error reloading: {}. Carrying on with the previous list.", error)),
+        Notice::GitBusy { repo } => (
+            Level::Warning,
+            format!("A git operation is running in {}, holding its changes until it finishes", repo.display()),
+        ),
+        Notice::GitFinished { repo, held } => (
+            Level::Success,
+            format!("The git operation in {} finished, running the action(s) once for {} held change(s)", repo.display(), held),
+        ),
+        Notice::Changed(_) | Notice::ActionStarted { .. } => return None,
+        Notice::ActionFinished { output, .. } => (Level::Output, output),
+        Notice::ActionFailed { action, error, .. } => (Level::Error, format!("Error running {}: {}", action, error)),
+    };
+    Some(message)
+}
+
+/// How much of an action's output goes into an `action_finish` record.
+const EVENT_OUTPUT_LIMIT: usize = 4096;
+
+/// The messages of `stalk execute --events jsonl` itself, as `notice`
+/// records, so that every line of its output is a JSON object.
+struct EventReporter;
+
+impl Reporter for EventReporter {
+    fn report(&mut self, level: Level, message: &str) {
+        print_record(json!({
+            "type": "notice",
+            "level": level.as_str(),
+            "message": message,
+        }));
+    }
+}
+
+/// Prints a notice as a line of `stalk execute --events jsonl`.
+fn print_event(notice: Notice) {
+    let record = match notice {
+        Notice::Changed(change) => json!({
+            "type": "event",
+            "path": change.path.to_string_lossy(),
+            "kind": change.kind.as_str(),
+            "root": change.root.to_string_lossy(),
+        }),
+        Notice::ActionStarted { action, id, path } => json!({
+            "type": "action_start",
+            "action": action,
+            "action_id": id,
+            "path": path.to_string_lossy(),
+        }),
+        Notice::ActionFinished { action, id, path, output, exit_code, duration } => {
+            let (output, truncated) = truncate_output(&output);
+            json!({
+                "type": "action_finish",
+                "action": action,
+                "action_id": id,
+                "path": path.to_string_lossy(),
+                "exit_code": exit_code,
+                "duration_ms": duration.as_millis() as u64,
+                "output": output,
+                "truncated": truncated,
+                "error": null,
+            })
+        }
+        Notice::ActionFailed { action, id, path, error, duration } => json!({
+            "type": "action_finish",
+            "action": action,
+            "action_id": id,
+            "path": path.to_string_lossy(),
+            "exit_code": null,
+            "duration_ms": duration.as_millis() as u64,
+            "output": "",
+            "truncated": false,
+            "error": error,
+        }),
+        other => {
+            if let Some((level, message)) = notice_message(other) {
+                EventReporter.report(level, &message);
+            }
+            return;
+        }
+    };
+    print_record(record);
+}
+
+fn print_record(mut record: serde_json::Value) {
+    let timestamp_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64);
+    record["timestamp_ms"] = json!(timestamp_ms);
+    println!("{}", record);
+}
+
+// The start of `output` that fits in an `action_finish` record, cut at a
+// character boundary, and whether anything was cut off.
+fn truncate_output(output: &str) -> (&str, bool) {
+    let mut end = output.len().min(EVENT_OUTPUT_LIMIT);
+    while !output.is_char_boundary(end) {
+        end -= 1;
+    }
+    (&output[..end], end < output.len())
+}
+
+/// Exit codes, as documented in the README. clap exits with 2 on usage errors.
+fn exit_code(error: &StalkerError) -> i32 {
+    match error {
+        StalkerError::NotFound(_) => 1,
+        StalkerError::InvalidInput(_) => 2,
+        StalkerError::NoInstance(_) => 3,
+        StalkerError::Parse { .. } => 4,
+        StalkerError::Io { .. } => 5,
+        StalkerError::Watch(_) => 6,
+        StalkerError::Editor(_) => 7,
+        StalkerError::Daemon(_) => 8,
+    }
+}
+
+// The stalker still runs without a control socket or signals, it just
+// can't be controlled.
+#[cfg(unix)]
+fn serve_control(reporter: &mut dyn Reporter, stalker_instance: &Path, controller: Controller) -> Option<ControlServer> {
+    if let Err(e) = stalker::handle_signals(controller.clone()) {
+        reporter.report(Level::Warning, &format!("Warning: SIGUSR1 and SIGUSR2 won't pause and resume this stalker: {}", e));
+    }
+    match stalker::listen_control(stalker_instance, controller) {
+        Ok(server) => Some(server),
+        Err(e) => {
+            reporter.report(Level::Warning, &format!("Warning: 'stalk ctl' can't reach this stalker: {}", e));
+            None
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn serve_control(_reporter: &mut dyn Reporter, _stalker_instance: &Path, _controller: Controller) -> Option<()> {
+    None
+}
+
+/// The line of the control protocol a `stalk ctl` subcommand sends.
+fn ctl_request(command: &str, args: &clap::ArgMatches) -> Result<String, StalkerError> {
+    if let Some(mode) = args.try_get_one::<String>("mode").ok().flatten() {
+        return Ok(format!("{} {}", command, mode));
+    }
+    match args.try_get_one::<String>("PATH").ok().flatten() {
+        Some(path) => {
+            let path = paths::normalize(Path::new(path)).map_err(StalkerError::InvalidInput)?;
+            Ok(format!("{} {}", command, path.display()))
+        }
+        None => Ok(command.to_string()),
+    }
+}
+
+#[cfg(unix)]
+fn send_control(stalker_instance: &Path, request: &str) -> Result<serde_json::Value, StalkerError> {
+    stalker::send_control(stalker_instance, request)
+}
+
+#[cfg(not(unix))]
+fn send_control(_stalker_instance: &Path, _request: &str) -> Result<serde_json::Value, StalkerError> {
+    Err(StalkerError::Daemon(String::from("the control socket is only supported on Unix")))
+}
+
+fn report_control(reporter: &mut dyn Reporter, response: &serde_json::Value) {
+    if let Some(children) = response["children"].as_array() {
+        if children.is_empty() {
+            reporter.report(Level::Item, "No actions are running");
+        }
+        for child in children {
+            let pid = child["pid"].as_u64().map_or_else(|| String::from("-"), |pid| pid.to_string());
+            reporter.report(Level::Item, &format!(
+                "pid {}: {} for {} (running for {}s)",
+                pid,
+                child["action"].as_str().unwrap_or_default(),
+                child["path"].as_str().unwrap_or_default(),
+                child["running_ms"].as_u64().unwrap_or_default() / 1000
+            ));
+        }
+    } else if let Some(message) = response["message"].as_str() {
+        reporter.report(Level::Success, &format!("stalker {}", message));
+    }
+}
+
+/// The arguments `stalk execute --daemon` starts itself again with in the background.
+fn daemon_args() -> Vec<OsString> {
+    env::args_os()
+        .skip(1)
+        .filter(|arg| arg != "--daemon")
+        .chain([OsString::from("--detached")])
+        .collect()
+}
+
+/// Reports the error of a failed command and returns the exit code for it.
+fn exit_status(reporter: &mut dyn Reporter, result: Result<(), StalkerError>) -> i32 {
+    let error = match result {
+        Ok(_) => return 0,
+        Err(e) => e,
+    };
+    match &error {
+        // The instance is there, but no stalker is running for it.
+        StalkerError::NoInstance(path) if path.is_dir() => {
+            reporter.report(Level::Error, &format!("Error: stalker isn't running for {}", path.display()));
+            reporter.report(Level::Warning, "HINT: Start it with \"stalk execute\".");
+        }
+        StalkerError::NoInstance(_) => {
+            reporter.report(Level::Error, &format!("Error: {}", error));
+            reporter.report(Level::Warning, "HINT: Run \"stalk init\" first to create a stalker instance.");
+        }
+        _ => reporter.report(Level::Error, &format!("Error: {}", error)),
+    }
+    exit_code(&error)
+}
+
+// Nothing matching at all is reported per item, so it only changes the exit code.
+fn removal_status<T>(reporter: &mut dyn Reporter, result: Result<Removed<T>, StalkerError>, list: &str, describe: impl Fn(&T) -> String) -> i32 {
+    let removed = match result {
+        Ok(removed) => removed,
+        Err(e) => return exit_status(reporter, Err(e)),
+    };
+    for item in &removed.removed {
+        reporter.report(Level::Success, &format!("Successfully removed {} from {}", describe(item), list));
+    }
+    for item in &removed.unmatched {
+        reporter.report(Level::Error, &format!("Error removing {}: nothing in the {} matches it", item, list));
+    }
+    if removed.removed.is_empty() {
+        exit_code(&StalkerError::NotFound(String::new()))
+    } else {
+        0
+    }
+}
+
+// Multi-item commands carry on after a failing item and exit with the first
+// failure's code.
+fn first_failure(status: i32, next: i32) -> i32 {
+    if status == 0 {
+        next
+    } else {
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::path::PathBuf;
+
+    // Keeps what was reported, to check it.
+    #[derive(Default)]
+    struct Recorded(Vec<(Level, String)>);
+
+    impl Reporter for Recorded {
+        fn report(&mut self, level: Level, message: &str) {
+            self.0.push((level, message.to_string()));
+        }
+    }
+
+    #[test]
+    fn each_kind_of_error_has_its_own_exit_code() {
+        let io = || io::Error::other("failed");
+        let errors = [
+            (StalkerError::NotFound(String::new()), 1),
+            (StalkerError::InvalidInput(String::new()), 2),
+            (StalkerError::NoInstance(PathBuf::new()), 3),
+            (StalkerError::Parse { path: PathBuf::new(), message: String::new() }, 4),
+            (StalkerError::io(Path::new(""), io()), 5),
+            (StalkerError::Watch(notify::Error::Io(io())), 6),
+            (StalkerError::Editor(String::new()), 7),
+            (StalkerError::Daemon(String::new()), 8),
+        ];
+        for (error, code) in errors {
+            assert_eq!(exit_code(&error), code, "{:?}", error);
+        }
+    }
+
+    #[test]
+    fn removals_fail_only_when_nothing_was_removed() {
+        let describe = |item: &&str| item.to_string();
+        let mut reporter = Recorded::default();
+        let partly = Ok(Removed {
+            removed: vec!["a"],
+            unmatched: vec![String::from("b")],
+        });
+        assert_eq!(removal_status(&mut reporter, partly, "stalklist", describe), 0);
+        assert_eq!(
+            reporter.0,
+            [
+                (Level::Success, String::from("Successfully removed a from stalklist")),
+                (Level::Error, String::from("Error removing b: nothing in the stalklist matches it")),
+            ]
+        );
+
+        let nothing = Ok(Removed {
+            removed: Vec::new(),
+            unmatched: vec![String::from("b")],
+        });
+        assert_eq!(removal_status(&mut Recorded::default(), nothing, "stalklist", describe), 1);
+        let empty = Err(StalkerError::NotFound(String::from("the stalklist is empty")));
+        assert_eq!(removal_status(&mut Recorded::default(), empty, "stalklist", describe), 1);
+        let no_instance = Err(StalkerError::NoInstance(PathBuf::from("/nowhere")));
+        assert_eq!(removal_status(&mut Recorded::default(), no_instance, "stalklist", describe), 3);
+    }
+
+    #[test]
+    fn action_output_is_truncated_at_a_character_boundary() {
+        let short = "ok\n";
+        assert_eq!(truncate_output(short), (short, false));
+
+        let exact = "a".repeat(EVENT_OUTPUT_LIMIT);
+        assert_eq!(truncate_output(&exact), (exact.as_str(), false));
+
+        let long = "a".repeat(EVENT_OUTPUT_LIMIT + 1);
+        assert_eq!(truncate_output(&long), (&long[..EVENT_OUTPUT_LIMIT], true));
+
+        // "é" is two bytes, and the limit falls between them.
+        let split = format!("{}é", "a".repeat(EVENT_OUTPUT_LIMIT - 1));
+        assert_eq!(truncate_output(&split), (&split[..EVENT_OUTPUT_LIMIT - 1], true));
     }
 }
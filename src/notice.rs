@@ -0,0 +1,78 @@
+use crate::control::PauseMode;
+use crate::entry::WatchEntry;
+use crate::event::Change;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Something a running stalker reports while it keeps going. Failures that
+/// stop it are returned as a `StalkerError` instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Notice {
+    /// A stalklist path that didn't exist yet has appeared and is now watched.
+    Appeared(PathBuf),
+    /// A stalklist path doesn't exist yet; `ancestor` is watched until it appears.
+    Pending { path: PathBuf, ancestor: PathBuf },
+    /// A symlink pointing back at one of its own ancestors was skipped.
+    SymlinkCycle(PathBuf),
+    /// Part of a stalklist path couldn't be walked, or couldn't be watched at all.
+    WatchFailed { path: PathBuf, error: String },
+    /// The env_file was read again after it changed.
+    EnvFileReloaded(PathBuf),
+    /// The env_file couldn't be read; the previous variables are kept.
+    EnvFileFailed { path: PathBuf, error: String },
+    /// `path` kept triggering, possibly by its own actions, and is ignored for `ignored_for`.
+    LoopDetected {
+        path: PathBuf,
+        count: usize,
+        ignored_for: Duration,
+    },
+    /// Actions stopped running for changes until the stalker is resumed.
+    Paused(PauseMode),
+    /// The stalker was resumed, and runs the actions for the `held` changes
+    /// as `mode` says.
+    Resumed { mode: PauseMode, held: usize },
+    /// The config.txt of the instance was read again and applied.
+    ConfigReloaded(PathBuf),
+    /// An entry added to the stalklist while running is now watched.
+    Watching(WatchEntry),
+    /// An entry removed from the stalklist while running is no longer watched.
+    Unwatched(PathBuf),
+    /// An action was added to the actionlist while running; `action` includes its options.
+    ActionAdded { action: String, id: Option<u32> },
+    ActionRemoved { action: String, id: Option<u32> },
+    /// The stalklist or actionlist changed but couldn't be read; the stalker
+    /// carries on with what it had.
+    ReloadFailed { path: PathBuf, error: String },
+    /// A git operation is rewriting the work tree of `repo`; changes in it
+    /// are held until it has finished.
+    GitBusy { repo: PathBuf },
+    /// The git operation in `repo` has finished, and the actions run once
+    /// for the `held` changes.
+    GitFinished { repo: PathBuf, held: usize },
+    /// A change under a stalklist path came in. Actions only run for it if
+    /// it isn't suppressed as their own doing.
+    Changed(Change),
+    /// An action is about to run for the change to `path`.
+    ActionStarted {
+        action: String,
+        id: Option<u32>,
+        path: PathBuf,
+    },
+    /// An action ran; carries its output, e.g. what a command wrote to stdout.
+    ActionFinished {
+        action: String,
+        id: Option<u32>,
+        path: PathBuf,
+        output: String,
+        exit_code: Option<i32>,
+        duration: Duration,
+    },
+    /// An action failed, e.g. because its command couldn't be started.
+    ActionFailed {
+        action: String,
+        id: Option<u32>,
+        path: PathBuf,
+        error: String,
+        duration: Duration,
+    },
+}
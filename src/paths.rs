@@ -0,0 +1,144 @@
+use std::env;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Turns a path as typed by the user into the absolute form stored in the
+/// stalklist: `~` and `$VAR`/`${VAR}` are expanded, relative paths are
+/// resolved against the current directory, and symlinks in the existing
+/// part of the path are resolved.
+pub fn normalize(input: &Path) -> Result<PathBuf, String> {
+    let raw = input
+        .to_str()
+        .ok_or_else(|| format!("{} is not valid UTF-8", input.display()))?;
+    let expanded = PathBuf::from(expand_vars(&expand_tilde(raw)?)?);
+
+    let absolute = if expanded.is_absolute() {
+        expanded
+    } else {
+        env::current_dir()
+            .map_err(|e| format!("cannot resolve {}: {}", raw, e))?
+            .join(expanded)
+    };
+    Ok(canonicalize_existing(&lexical_normalize(&absolute)))
+}
+
+fn expand_tilde(raw: &str) -> Result<String, String> {
+    match raw.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') || rest.starts_with('\\') => {
+            let home = dirs::home_dir().ok_or("cannot find $HOME directory")?;
+            Ok(format!("{}{}", home.display(), rest))
+        }
+        _ => Ok(raw.to_string()),
+    }
+}
+
+fn expand_vars(raw: &str) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = raw;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let (name, remainder) = match after.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) => (&braced[..end], &braced[end + 1..]),
+                None => return Err(format!("unclosed '${{' in {}", raw)),
+            },
+            None => {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                (&after[..end], &after[end..])
+            }
+        };
+        if name.is_empty() {
+            expanded.push('$');
+        } else {
+            let value = env::var(name)
+                .map_err(|_| format!("environment variable {} is not set", name))?;
+            expanded.push_str(&value);
+        }
+        rest = remainder;
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Removes `.` and `..` components without touching the filesystem.
+fn lexical_normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+/// Canonicalizes the longest existing prefix of `path` and appends the rest,
+/// so paths that don't exist yet still get a stable form.
+fn canonicalize_existing(path: &Path) -> PathBuf {
+    for ancestor in path.ancestors() {
+        if let Ok(canonical) = fs::canonicalize(ancestor) {
+            return match path.strip_prefix(ancestor) {
+                Ok(rest) if !rest.as_os_str().is_empty() => canonical.join(rest),
+                _ => canonical,
+            };
+        }
+    }
+    path.to_path_buf()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dot_components_are_removed_and_missing_parts_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = fs::canonicalize(dir.path()).unwrap();
+        fs::create_dir(base.join("a")).unwrap();
+
+        let input = base.join("a/./../a/not/yet");
+        assert_eq!(normalize(&input).unwrap(), base.join("a/not/yet"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_in_the_existing_part_are_resolved() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = fs::canonicalize(dir.path()).unwrap();
+        fs::create_dir(base.join("real")).unwrap();
+        std::os::unix::fs::symlink(base.join("real"), base.join("link")).unwrap();
+
+        assert_eq!(normalize(&base.join("link/file")).unwrap(), base.join("real/file"));
+    }
+
+    #[test]
+    fn tilde_and_variables_are_expanded() {
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(expand_tilde("~/x").unwrap(), format!("{}/x", home.display()));
+        assert_eq!(expand_tilde("~user/x").unwrap(), "~user/x");
+
+        let path = env::var("PATH").unwrap();
+        assert_eq!(expand_vars("$PATH/a").unwrap(), format!("{}/a", path));
+        assert_eq!(expand_vars("${PATH}b$").unwrap(), format!("{}b$", path));
+    }
+
+    #[test]
+    fn unset_variables_and_unclosed_braces_are_errors() {
+        assert_eq!(
+            expand_vars("/a/$STALKER_TEST_UNSET_VARIABLE").unwrap_err(),
+            "environment variable STALKER_TEST_UNSET_VARIABLE is not set"
+        );
+        assert_eq!(expand_vars("/a/${PATH").unwrap_err(), "unclosed '${' in /a/${PATH");
+    }
+
+    #[test]
+    fn lexical_normalize_does_not_go_above_the_root() {
+        assert_eq!(lexical_normalize(Path::new("/a/../../b/./c")), PathBuf::from("/b/c"));
+    }
+}
@@ -0,0 +1,123 @@
+use crossterm::execute;
+use crossterm::style::{Color, Print, ResetColor, SetForegroundColor};
+use serde_json::json;
+use std::env;
+use std::io::{stdout, IsTerminal};
+
+/// What kind of message is being reported, which decides how it is shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Success,
+    /// An item of a listing, e.g. a stalklist entry.
+    Item,
+    Warning,
+    Error,
+    /// Output of an action, shown as is.
+    Output,
+}
+
+impl Level {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Success => "success",
+            Level::Item => "item",
+            Level::Warning => "warning",
+            Level::Error => "error",
+            Level::Output => "output",
+        }
+    }
+}
+
+/// Where the messages of the `stalk` binary go.
+pub trait Reporter: Send {
+    fn report(&mut self, level: Level, message: &str);
+}
+
+/// Colored text on stdout.
+pub struct ColorReporter;
+
+impl Reporter for ColorReporter {
+    fn report(&mut self, level: Level, message: &str) {
+        let color = match level {
+            Level::Success => Color::Green,
+            Level::Item => Color::Blue,
+            Level::Warning => Color::Yellow,
+            Level::Error => Color::Red,
+            Level::Output => return println!("{}", message),
+        };
+        match execute!(
+            stdout(),
+            SetForegroundColor(color),
+            Print(format!("{}\n", message)),
+            ResetColor
+        ) {
+            Ok(_) => {}
+            Err(e) => eprintln!("Error printing output: {}", e),
+        }
+    }
+}
+
+/// Text without escape codes on stdout, for pipes and files.
+pub struct PlainReporter;
+
+impl Reporter for PlainReporter {
+    fn report(&mut self, _level: Level, message: &str) {
+        println!("{}", message);
+    }
+}
+
+/// One JSON object per message on stdout, e.g.
+/// `{"level":"success","message":"Successfully created stalklist"}`.
+pub struct JsonReporter;
+
+impl Reporter for JsonReporter {
+    fn report(&mut self, level: Level, message: &str) {
+        println!("{}", json!({ "level": level.as_str(), "message": message }));
+    }
+}
+
+/// Only errors, on stderr.
+pub struct QuietReporter;
+
+impl Reporter for QuietReporter {
+    fn report(&mut self, level: Level, message: &str) {
+        if level == Level::Error {
+            eprintln!("{}", message);
+        }
+    }
+}
+
+/// When text output is colored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Color when stdout is a terminal and `NO_COLOR` isn't set.
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Quiet,
+}
+
+/// Picks the reporter for the `--output` and `--color` flags.
+pub fn reporter(output: OutputFormat, color: ColorChoice) -> Box<dyn Reporter> {
+    match (output, color) {
+        (OutputFormat::Json, _) => Box::new(JsonReporter),
+        (OutputFormat::Quiet, _) => Box::new(QuietReporter),
+        (OutputFormat::Text, ColorChoice::Always) => Box::new(ColorReporter),
+        (OutputFormat::Text, ColorChoice::Never) => Box::new(PlainReporter),
+        (OutputFormat::Text, ColorChoice::Auto) => {
+            // https://no-color.org: any non-empty value disables color.
+            let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+            if stdout().is_terminal() && !no_color {
+                Box::new(ColorReporter)
+            } else {
+                Box::new(PlainReporter)
+            }
+        }
+    }
+}
@@ -0,0 +1,1195 @@
+use crate::action::{read_actionlist, Action, ActionEntry, FnAction};
+use crate::config::{InstanceConfig, CONFIG_FILE};
+use crate::control::{Controller, PauseMode, Request, RunningAction};
+use crate::dotenv;
+use crate::entry::{read_stalklist, WatchEntry};
+use crate::error::StalkerError;
+use crate::event::{Change, ChangeKind};
+use crate::feedback::{FeedbackGuard, Stamp, Verdict};
+use crate::filter::EditorFilter;
+use crate::git::{GitMonitor, Repo};
+use crate::notice::Notice;
+use crate::source::{EventSource, Next, NotifySource};
+use crate::watch::{Added, WatchSet};
+use notify::DebouncedEvent;
+use std::collections::HashMap;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How long file events are collected before actions run for them.
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_secs(5);
+
+// How often a spawned stalker checks whether it was asked to stop.
+const STOP_POLL: Duration = Duration::from_millis(100);
+
+// How long a changed stalklist or actionlist has to stay unchanged before
+// it is applied, so it isn't read half-written.
+const LIST_SETTLE: Duration = Duration::from_millis(500);
+
+// How long after the last sign of a git operation its changes are still
+// held, on top of the event source's delay.
+const GIT_SETTLE: Duration = Duration::from_secs(1);
+
+type NoticeHandler = Box<dyn FnMut(Notice) + Send>;
+
+/// Configures a [`Stalker`] from code instead of an instance directory.
+///
+/// ```no_run
+/// use stalker::action::ActionEntry;
+/// use stalker::entry::WatchEntry;
+/// use stalker::Stalker;
+///
+/// let handle = Stalker::builder()
+///     .watch(WatchEntry::new("/home/me/notes"))
+///     .action(ActionEntry::new("git -C {root} add {path}"))
+///     .action_fn("log", |change| {
+///         println!("{} {}", change.kind, change.path.display());
+///         Ok(String::new())
+///     })
+///     .on_notice(|notice| eprintln!("{:?}", notice))
+///     .build()?
+///     .spawn()?;
+/// // ...
+/// handle.stop()?;
+/// # Ok::<(), stalker::error::StalkerError>(())
+/// ```
+pub struct StalkerBuilder {
+    instance: Option<PathBuf>,
+    entries: Vec<WatchEntry>,
+    /// The actionlist the first `actions` came from, to apply changes to it.
+    listed: Vec<ActionEntry>,
+    actions: Vec<Box<dyn Action>>,
+    config: InstanceConfig,
+    debounce: Duration,
+    source: Option<Box<dyn EventSource>>,
+    on_notice: NoticeHandler,
+}
+
+impl Default for StalkerBuilder {
+    fn default() -> Self {
+        StalkerBuilder {
+            instance: None,
+            entries: Vec::new(),
+            listed: Vec::new(),
+            actions: Vec::new(),
+            config: InstanceConfig::default(),
+            debounce: DEFAULT_DEBOUNCE,
+            source: None,
+            on_notice: Box::new(|_| {}),
+        }
+    }
+}
+
+impl StalkerBuilder {
+    /// Starts from the stalklist, actionlist and config of an instance
+    /// directory, as `stalk execute` does. Changes to the stalklist and
+    /// actionlist are applied while the stalker runs.
+    pub fn from_instance(stalker_instance: &Path) -> Result<StalkerBuilder, StalkerError> {
+        if !stalker_instance.is_dir() {
+            return Err(StalkerError::NoInstance(stalker_instance.to_path_buf()));
+        }
+        let listed = read_actionlist(stalker_instance)?;
+        Ok(StalkerBuilder {
+            instance: Some(stalker_instance.to_path_buf()),
+            entries: read_stalklist(stalker_instance)?,
+            actions: boxed(&listed),
+            listed,
+            config: InstanceConfig::load(stalker_instance)?,
+            ..StalkerBuilder::default()
+        })
+    }
+
+    /// Watches another path. Paths are used as given, so pass absolute ones
+    /// (see `paths::normalize`).
+    pub fn watch(mut self, entry: WatchEntry) -> StalkerBuilder {
+        self.entries.push(entry);
+        self
+    }
+
+    /// Runs another action on every change, after the ones added before it.
+    pub fn action(mut self, action: impl Action + 'static) -> StalkerBuilder {
+        self.actions.push(Box::new(action));
+        self
+    }
+
+    /// Runs a closure as an action; `name` is how it is shown in notices.
+    pub fn action_fn(
+        self,
+        name: impl Into<String>,
+        f: impl FnMut(&Change) -> Result<String, String> + Send + 'static,
+    ) -> StalkerBuilder {
+        self.action(FnAction::new(name, f))
+    }
+
+    pub fn config(mut self, config: InstanceConfig) -> StalkerBuilder {
+        self.config = config;
+        self
+    }
+
+    /// How long the default notify source collects events before passing them on.
+    pub fn debounce(mut self, debounce: Duration) -> StalkerBuilder {
+        self.debounce = debounce;
+        self
+    }
+
+    /// Takes events from `source` instead of a `NotifySource`, e.g. a
+    /// `PollSource` or a `ScriptedSource`.
+    pub fn event_source(mut self, source: impl EventSource + 'static) -> StalkerBuilder {
+        self.source = Some(Box::new(source));
+        self
+    }
+
+    /// Receives everything worth telling the user while the stalker runs.
+    /// Notices are dropped by default.
+    pub fn on_notice(mut self, on_notice: impl FnMut(Notice) + Send + 'static) -> StalkerBuilder {
+        self.on_notice = Box::new(on_notice);
+        self
+    }
+
+    pub fn build(self) -> Result<Stalker, StalkerError> {
+        let filter = EditorFilter::new(self.config.editor_filter, &self.config.ignore_patterns)
+            .map_err(|e| StalkerError::InvalidInput(format!("invalid ignore_patterns: {}", e)))?;
+        let (controller, requests) = Controller::new();
+        Ok(Stalker {
+            instance: self.instance,
+            entries: self.entries,
+            listed: self.listed,
+            actions: self.actions,
+            config: self.config,
+            debounce: self.debounce,
+            source: self.source,
+            filter,
+            on_notice: self.on_notice,
+            controller,
+            requests,
+        })
+    }
+}
+
+/// Watches a set of paths and runs actions whenever something under them changes.
+pub struct Stalker {
+    instance: Option<PathBuf>,
+    entries: Vec<WatchEntry>,
+    listed: Vec<ActionEntry>,
+    actions: Vec<Box<dyn Action>>,
+    config: InstanceConfig,
+    debounce: Duration,
+    source: Option<Box<dyn EventSource>>,
+    filter: EditorFilter,
+    on_notice: NoticeHandler,
+    controller: Controller,
+    requests: Receiver<Request>,
+}
+
+impl Stalker {
+    pub fn builder() -> StalkerBuilder {
+        StalkerBuilder::default()
+    }
+
+    /// A handle to pause, trigger, reload or shut down the stalker while it runs.
+    pub fn controller(&self) -> Controller {
+        self.controller.clone()
+    }
+
+    /// Watches and runs actions on the current thread until watching fails
+    /// or the event source finishes.
+    pub fn run(self) -> Result<(), StalkerError> {
+        self.start()?.run(&AtomicBool::new(false))
+    }
+
+    /// Like [`Stalker::run`], but also returns once `stop` is set, e.g. from a
+    /// signal handler.
+    pub fn run_until(self, stop: &AtomicBool) -> Result<(), StalkerError> {
+        self.start()?.run(stop)
+    }
+
+    /// Starts watching, then runs actions on a new thread until the returned
+    /// handle is stopped. Failing to watch is reported here rather than later.
+    pub fn spawn(self) -> Result<StalkerHandle, StalkerError> {
+        let running = self.start()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = Arc::clone(&stop);
+            thread::Builder::new()
+                .name(String::from("stalker"))
+                .spawn(move || running.run(&stop))
+                .map_err(|e| StalkerError::io(Path::new(""), e))?
+        };
+        Ok(StalkerHandle { stop, thread })
+    }
+
+    // Files outside the stalklist that the stalker reacts to: the env_file,
+    // and the instance's own lists, which are applied when they change.
+    fn files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = self.config.env_file.iter().cloned().collect();
+        if let Some(instance) = &self.instance {
+            files.push(instance.join("stalklist.txt"));
+            files.push(instance.join("actionlist.txt"));
+        }
+        files
+    }
+
+    // The paths whose repositories are checked for git operations.
+    fn git_paths(&self) -> Vec<PathBuf> {
+        if self.config.git_pause {
+            self.entries.iter().map(|entry| entry.path.clone()).collect()
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn start(mut self) -> Result<Running, StalkerError> {
+        let mut source = match self.source.take() {
+            Some(source) => source,
+            None => Box::new(NotifySource::new(self.debounce)?),
+        };
+        let mut watch_set = WatchSet::default();
+        for entry in &self.entries {
+            let added = watch_set.add(entry, source.as_mut(), &self.filter);
+            report_added(entry, added, &mut self.on_notice);
+        }
+
+        let mut env_file_vars = Vec::new();
+        if let Some(env_file) = &self.config.env_file {
+            env_file_vars = load_env_file(env_file, &mut self.on_notice).unwrap_or_default();
+        }
+        watch_files(&self.files(), &mut watch_set, source.as_mut(), &mut self.on_notice);
+        self.controller.set_pause_mode(self.config.pause_mode);
+
+        Ok(Running {
+            git: GitMonitor::new(&self.git_paths()),
+            git_held: Vec::new(),
+            guard: FeedbackGuard::new(&self.config),
+            noticed: HashMap::new(),
+            stalker: self,
+            source,
+            watch_set,
+            env_file_vars,
+            paused: None,
+            held: Vec::new(),
+            lists_changed: Vec::new(),
+        })
+    }
+}
+
+/// A stalker running on its own thread, as returned by [`Stalker::spawn`].
+pub struct StalkerHandle {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<Result<(), StalkerError>>,
+}
+
+impl StalkerHandle {
+    /// Stops watching once the actions currently running have finished, and
+    /// returns how the stalker ended.
+    pub fn stop(self) -> Result<(), StalkerError> {
+        self.stop.store(true, Ordering::SeqCst);
+        self.thread
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    }
+
+    /// Whether the stalker has ended on its own, e.g. because watching failed.
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+}
+
+// A started stalker: everything the event loop needs.
+struct Running {
+    stalker: Stalker,
+    source: Box<dyn EventSource>,
+    watch_set: WatchSet,
+    guard: FeedbackGuard,
+    /// Writes already reported by notify's early notice, with what the file
+    /// looked like then.
+    noticed: HashMap<PathBuf, Option<Stamp>>,
+    env_file_vars: Vec<(String, String)>,
+    /// How the stalker was paused when last checked, to act on changes to it.
+    paused: Option<PauseMode>,
+    /// Changes that came in while paused, at most one per walked path, to
+    /// run actions for on resume.
+    held: Vec<(PathBuf, Change)>,
+    /// Instance lists that changed and haven't been applied yet, with when
+    /// they last changed.
+    lists_changed: Vec<(PathBuf, Instant)>,
+    git: GitMonitor,
+    /// Changes held while git operations run, one per repository.
+    git_held: Vec<GitHold>,
+}
+
+// The changes in a repository held during a git operation. The actions run
+// once afterwards, for the last one with the paths of all of them.
+struct GitHold {
+    repo: Repo,
+    path: PathBuf,
+    change: Change,
+    paths: Vec<PathBuf>,
+    count: usize,
+}
+
+impl Running {
+    fn run(mut self, stop: &AtomicBool) -> Result<(), StalkerError> {
+        while !stop.load(Ordering::SeqCst) && !self.stalker.controller.is_shutdown() {
+            self.serve_requests();
+            self.apply_settled_lists();
+            self.release_git_holds();
+            match self.source.next(STOP_POLL)? {
+                Next::Event(event) => self.handle(event),
+                Next::Idle => {}
+                Next::Finished => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn serve_requests(&mut self) {
+        let paused = self.stalker.controller.paused();
+        if paused != self.paused {
+            let was = mem::replace(&mut self.paused, paused);
+            match (paused, was) {
+                (Some(mode), _) => (self.stalker.on_notice)(Notice::Paused(mode)),
+                (None, Some(mode)) => self.resume(mode),
+                (None, None) => {}
+            }
+        }
+        while let Ok(request) = self.stalker.requests.try_recv() {
+            // The requester may have given up waiting; that's fine.
+            match request {
+                Request::Trigger(path, reply) => {
+                    let _ = reply.send(self.trigger(path));
+                }
+                Request::Reload(reply) => {
+                    let _ = reply.send(self.reload());
+                }
+            }
+        }
+    }
+
+    fn release_git_holds(&mut self) {
+        let settle = self.source.delay() + GIT_SETTLE;
+        let (finished, busy): (Vec<GitHold>, Vec<GitHold>) = mem::take(&mut self.git_held)
+            .into_iter()
+            .partition(|hold| !self.git.is_busy(&hold.repo, settle));
+        self.git_held = busy;
+        for hold in finished {
+            (self.stalker.on_notice)(Notice::GitFinished {
+                repo: hold.repo.work_tree,
+                held: hold.count,
+            });
+            let change = Change {
+                paths: hold.paths,
+                ..hold.change
+            };
+            self.dispatch(&hold.path, change);
+        }
+    }
+
+    // Runs what was held while paused, as the mode the stalker was paused
+    // with asks for.
+    fn resume(&mut self, mode: PauseMode) {
+        let mut held = mem::take(&mut self.held);
+        if mode == PauseMode::Discard {
+            held.clear();
+        }
+        (self.stalker.on_notice)(Notice::Resumed {
+            mode,
+            held: held.len(),
+        });
+        if mode == PauseMode::Once {
+            // The last change stands for all of them.
+            let paths: Vec<PathBuf> = held.iter().map(|(_, change)| change.path.clone()).collect();
+            held.drain(..held.len().saturating_sub(1));
+            if let Some((_, change)) = held.last_mut() {
+                change.paths = paths;
+            }
+        }
+        for (path, change) in held {
+            self.dispatch(&path, change);
+        }
+    }
+
+    fn trigger(&mut self, path: Option<PathBuf>) -> Result<usize, StalkerError> {
+        let resolved: Vec<(PathBuf, PathBuf)> = match path {
+            Some(path) => match self.watch_set.resolve(path.clone()) {
+                Some(resolved) => vec![resolved],
+                None => {
+                    return Err(StalkerError::NotFound(format!(
+                        "{} is not watched",
+                        path.display()
+                    )))
+                }
+            },
+            // Paths still waiting to appear are skipped.
+            None => self
+                .stalker
+                .entries
+                .iter()
+                .filter_map(|entry| self.watch_set.resolve(entry.path.clone()))
+                .collect(),
+        };
+        for (path, root) in &resolved {
+            let change = Change::new(self.watch_set.reported(path), ChangeKind::Trigger, root.clone());
+            (self.stalker.on_notice)(Notice::Changed(change.clone()));
+            self.dispatch(path, change);
+        }
+        Ok(resolved.len())
+    }
+
+    fn reload(&mut self) -> Result<(), StalkerError> {
+        let instance = self.stalker.instance.clone().ok_or_else(|| {
+            StalkerError::InvalidInput(String::from(
+                "the stalker wasn't started from an instance, there is nothing to reload",
+            ))
+        })?;
+        let config = InstanceConfig::load(&instance)?;
+        let filter = EditorFilter::new(config.editor_filter, &config.ignore_patterns)
+            .map_err(|e| StalkerError::InvalidInput(format!("invalid ignore_patterns: {}", e)))?;
+        let env_file_changed = config.env_file != self.stalker.config.env_file;
+        self.guard.reconfigure(&config);
+        self.stalker.controller.set_pause_mode(config.pause_mode);
+        self.stalker.config = config;
+        self.stalker.filter = filter;
+        self.git = self.git.renewed(&self.stalker.git_paths());
+        if env_file_changed {
+            self.env_file_vars = match &self.stalker.config.env_file {
+                Some(env_file) => load_env_file(env_file, &mut self.stalker.on_notice).unwrap_or_default(),
+                None => Vec::new(),
+            };
+            let files = self.stalker.files();
+            watch_files(&files, &mut self.watch_set, self.source.as_mut(), &mut self.stalker.on_notice);
+        }
+        (self.stalker.on_notice)(Notice::ConfigReloaded(instance.join(CONFIG_FILE)));
+        self.reload_stalklist(&instance)?;
+        self.reload_actionlist(&instance)
+    }
+
+    // Applies changes to the stalklist: entries that are gone stop being
+    // watched and new ones start, while unchanged ones carry on.
+    fn reload_stalklist(&mut self, instance: &Path) -> Result<(), StalkerError> {
+        let entries = read_stalklist(instance)?;
+        if entries == self.stalker.entries {
+            return Ok(());
+        }
+        let files = self.stalker.files();
+        let Stalker {
+            entries: old,
+            filter,
+            on_notice,
+            ..
+        } = &mut self.stalker;
+        for entry in old.iter().filter(|entry| !entries.contains(entry)) {
+            on_notice(Notice::Unwatched(entry.path.clone()));
+        }
+        let mut watch_set = WatchSet::default();
+        for entry in &entries {
+            let added = watch_set.add(entry, self.source.as_mut(), filter);
+            if !old.contains(entry) {
+                on_notice(Notice::Watching(entry.clone()));
+                report_added(entry, added, on_notice);
+            }
+        }
+        watch_files(&files, &mut watch_set, self.source.as_mut(), on_notice);
+        // Roots the new set watches as well are kept, so nothing is missed
+        // in between.
+        let dropped: Vec<PathBuf> = self
+            .watch_set
+            .roots()
+            .filter(|root| !watch_set.roots().any(|kept| kept == *root))
+            .map(|(root, _)| root.to_path_buf())
+            .collect();
+        for root in &dropped {
+            let _ = self.source.unwatch(root);
+        }
+        // Backends such as inotify watch each directory once, so unwatching a
+        // root also drops the directories it shares with roots that are kept.
+        for (root, mode) in watch_set.roots() {
+            if dropped
+                .iter()
+                .any(|gone| root.starts_with(gone) || gone.starts_with(root))
+            {
+                let _ = self.source.watch(root, mode);
+            }
+        }
+        self.watch_set = watch_set;
+        *old = entries;
+        self.git = self.git.renewed(&self.stalker.git_paths());
+        Ok(())
+    }
+
+    // Applies changes to the actionlist. Actions added in code stay after
+    // the listed ones.
+    fn reload_actionlist(&mut self, instance: &Path) -> Result<(), StalkerError> {
+        let listed = read_actionlist(instance)?;
+        let stalker = &mut self.stalker;
+        if listed == stalker.listed {
+            return Ok(());
+        }
+        for action in stalker.listed.iter().filter(|action| !listed.contains(action)) {
+            (stalker.on_notice)(Notice::ActionRemoved {
+                action: action.to_string(),
+                id: action.id,
+            });
+        }
+        for action in listed.iter().filter(|action| !stalker.listed.contains(action)) {
+            (stalker.on_notice)(Notice::ActionAdded {
+                action: action.to_string(),
+                id: action.id,
+            });
+        }
+        let added_in_code = stalker.actions.split_off(stalker.listed.len());
+        stalker.actions = boxed(&listed);
+        stalker.actions.extend(added_in_code);
+        stalker.listed = listed;
+        Ok(())
+    }
+
+    fn instance_file_changed(&mut self, changed: &Path) {
+        let is_list = self.stalker.instance.as_ref().is_some_and(|instance| {
+            changed == instance.join("stalklist.txt") || changed == instance.join("actionlist.txt")
+        });
+        if is_list {
+            self.lists_changed.retain(|(list, _)| list != changed);
+            self.lists_changed.push((changed.to_path_buf(), Instant::now()));
+        }
+    }
+
+    fn apply_settled_lists(&mut self) {
+        let instance = match &self.stalker.instance {
+            Some(instance) => instance.clone(),
+            None => return,
+        };
+        let (settled, unsettled) = self
+            .lists_changed
+            .drain(..)
+            .partition(|(_, changed)| changed.elapsed() >= LIST_SETTLE);
+        self.lists_changed = unsettled;
+        for (list, _) in settled {
+            let reloaded = if list == instance.join("stalklist.txt") {
+                self.reload_stalklist(&instance)
+            } else {
+                self.reload_actionlist(&instance)
+            };
+            // The running lists are kept until the file can be read again.
+            if let Err(e) = reloaded {
+                (self.stalker.on_notice)(Notice::ReloadFailed {
+                    path: list,
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+
+    fn handle(&mut self, event: DebouncedEvent) {
+        // notify reports a write as soon as it starts and again once it has
+        // settled. The second report is only a change of its own if the file
+        // was written again in between.
+        match &event {
+            DebouncedEvent::NoticeWrite(path) => {
+                self.noticed.insert(path.clone(), Stamp::of(path));
+            }
+            DebouncedEvent::Write(path) if self.noticed.remove(path) == Some(Stamp::of(path)) => return,
+            DebouncedEvent::NoticeRemove(path) | DebouncedEvent::Remove(path) | DebouncedEvent::Rename(path, _) => {
+                self.noticed.remove(path);
+            }
+            _ => {}
+        }
+        let (changed, mut kind) = match ChangeKind::classify(event) {
+            Some(change) => change,
+            None => return,
+        };
+        // Atomic saves write a temp file and rename it over the real one, so
+        // the real file is created or renamed to rather than written.
+        if kind != ChangeKind::Write && self.watch_set.resolve(changed.clone()).is_some() {
+            kind = ChangeKind::Write;
+        }
+        self.instance_file_changed(&changed);
+        let Running {
+            stalker,
+            source,
+            watch_set,
+            env_file_vars,
+            ..
+        } = self;
+        let on_notice = &mut stalker.on_notice;
+
+        if stalker.config.env_file.as_ref() == Some(&changed) {
+            // Keep the previous variables if the new file doesn't parse.
+            if let Some(vars) = load_env_file(&changed, on_notice) {
+                *env_file_vars = vars;
+                on_notice(Notice::EnvFileReloaded(changed.clone()));
+            }
+        }
+        if stalker.filter.is_ignored(&changed) {
+            return;
+        }
+        // Entries that appear along with their parents had no events of
+        // their own, so they count as created.
+        let mut changes = Vec::new();
+        for (entry, added) in watch_set.created(&changed, source.as_mut(), &stalker.filter) {
+            if matches!(added, Ok(Added::Active(_))) {
+                on_notice(Notice::Appeared(entry.path.clone()));
+                changes.extend(watch_set.walked(&entry.path).into_iter().map(|path| (path, ChangeKind::Create)));
+            }
+            report_added(&entry, added, on_notice);
+        }
+        match changes.iter_mut().find(|(path, _)| *path == changed) {
+            Some((_, created)) => *created = kind,
+            None => changes.push((changed, kind)),
+        }
+        for (changed, kind) in changes {
+            let (path, root) = match self.watch_set.resolve(changed) {
+                Some(resolved) => resolved,
+                None => continue,
+            };
+            let change = Change::new(self.watch_set.reported(&path), kind, root);
+            (self.stalker.on_notice)(Notice::Changed(change.clone()));
+            self.dispatch(&path, change);
+        }
+    }
+
+    // Runs the actions for a change to the walked `path`, or holds it while
+    // paused or while git rewrites its repository. Triggered changes run
+    // even then, and aren't subject to the feedback guard.
+    fn dispatch(&mut self, path: &Path, change: Change) {
+        let Running {
+            stalker,
+            source,
+            guard,
+            watch_set,
+            env_file_vars,
+            held,
+            git,
+            git_held,
+            ..
+        } = self;
+        let on_notice = &mut stalker.on_notice;
+        let controller = &stalker.controller;
+
+        if change.kind != ChangeKind::Trigger {
+            // What git does to its own directory isn't a change to the work tree.
+            if git.repo_of(path).is_some_and(|repo| path.starts_with(&repo.git_dir)) {
+                return;
+            }
+            match controller.paused() {
+                None => {}
+                Some(PauseMode::Discard) => return,
+                Some(PauseMode::Accumulate | PauseMode::Once) => {
+                    held.retain(|(held_path, _)| held_path != path);
+                    held.push((path.to_path_buf(), change));
+                    return;
+                }
+            }
+            if let Some(repo) = git.repo_of(path) {
+                if git.is_busy(repo, source.delay() + GIT_SETTLE) {
+                    match git_held.iter_mut().find(|hold| hold.repo == *repo) {
+                        Some(hold) => {
+                            hold.paths.retain(|held| *held != change.path);
+                            hold.paths.push(change.path.clone());
+                            hold.path = path.to_path_buf();
+                            hold.change = change;
+                            hold.count += 1;
+                        }
+                        None => {
+                            on_notice(Notice::GitBusy {
+                                repo: repo.work_tree.clone(),
+                            });
+                            git_held.push(GitHold {
+                                repo: repo.clone(),
+                                path: path.to_path_buf(),
+                                paths: vec![change.path.clone()],
+                                change,
+                                count: 1,
+                            });
+                        }
+                    }
+                    return;
+                }
+            }
+            match guard.check(path) {
+                Verdict::Run => {}
+                Verdict::Suppressed | Verdict::Muted => return,
+                Verdict::LoopDetected(count) => {
+                    on_notice(Notice::LoopDetected {
+                        path: change.path,
+                        count,
+                        ignored_for: guard.loop_window(),
+                    });
+                    return;
+                }
+            }
+        }
+
+        // The actions may write to any watched file, not just to `path`.
+        guard.started(watch_set.paths());
+        for action in &mut stalker.actions {
+            let (name, id) = (action.describe(), action.id());
+            on_notice(Notice::ActionStarted {
+                action: name.clone(),
+                id,
+                path: change.path.clone(),
+            });
+            let started = Instant::now();
+            controller.action_started(RunningAction {
+                action: name.clone(),
+                id,
+                path: change.path.clone(),
+                pid: None,
+                started,
+            });
+            let result = action.run_tracked(&change, env_file_vars, &mut |pid| {
+                controller.action_spawned(pid)
+            });
+            controller.action_finished();
+            let duration = started.elapsed();
+            on_notice(match result {
+                Ok(ran) => Notice::ActionFinished {
+                    action: name,
+                    id,
+                    path: change.path.clone(),
+                    output: ran.output,
+                    exit_code: ran.exit_code,
+                    duration,
+                },
+                Err(error) => Notice::ActionFailed {
+                    action: name,
+                    id,
+                    path: change.path.clone(),
+                    error,
+                    duration,
+                },
+            });
+        }
+        guard.finished();
+    }
+}
+
+fn boxed(actions: &[ActionEntry]) -> Vec<Box<dyn Action>> {
+    actions
+        .iter()
+        .map(|action| Box::new(action.clone()) as Box<dyn Action>)
+        .collect()
+}
+
+fn watch_files(files: &[PathBuf], watch_set: &mut WatchSet, source: &mut dyn EventSource, on_notice: &mut NoticeHandler) {
+    for file in files {
+        if let Err(e) = watch_set.watch_file(file, source) {
+            on_notice(Notice::WatchFailed {
+                path: file.clone(),
+                error: e.to_string(),
+            });
+        }
+    }
+}
+
+fn load_env_file(path: &Path, on_notice: &mut NoticeHandler) -> Option<Vec<(String, String)>> {
+    match dotenv::load(path) {
+        Ok(vars) => Some(vars),
+        Err(error) => {
+            on_notice(Notice::EnvFileFailed {
+                path: path.to_path_buf(),
+                error,
+            });
+            None
+        }
+    }
+}
+
+fn report_added(entry: &WatchEntry, added: notify::Result<Added>, on_notice: &mut NoticeHandler) {
+    match added {
+        Ok(Added::Active(errors)) => {
+            for e in errors {
+                let path = e.path().unwrap_or(&entry.path).to_path_buf();
+                if e.loop_ancestor().is_some() {
+                    on_notice(Notice::SymlinkCycle(path));
+                } else {
+                    on_notice(Notice::WatchFailed {
+                        path,
+                        error: e.to_string(),
+                    });
+                }
+            }
+        }
+        Ok(Added::Pending(ancestor)) => on_notice(Notice::Pending {
+            path: entry.path.clone(),
+            ancestor,
+        }),
+        Err(e) => on_notice(Notice::WatchFailed {
+            path: entry.path.clone(),
+            error: e.to_string(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::ScriptedSource;
+    use std::sync::mpsc::Sender;
+    use std::sync::Mutex;
+
+    // A temporary directory with `files` in it, and its canonical path.
+    fn tree(files: &[&str]) -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let base = std::fs::canonicalize(dir.path()).unwrap();
+        for file in files {
+            let path = base.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        (dir, base)
+    }
+
+    // Runs a stalker watching `entry` until the events `send` sends are used
+    // up, returning the changes its action was run for. Sending starts once
+    // the stalker is watching.
+    fn dispatched(entry: WatchEntry, send: impl FnOnce(&Sender<DebouncedEvent>)) -> Vec<Change> {
+        let (source, events) = ScriptedSource::channel();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&seen);
+        let handle = Stalker::builder()
+            .watch(entry)
+            .action_fn("record", move |change| {
+                recorded.lock().unwrap().push(change.clone());
+                Ok(String::new())
+            })
+            .config(InstanceConfig {
+                git_pause: false,
+                ..InstanceConfig::default()
+            })
+            .event_source(source)
+            .build()
+            .unwrap()
+            .spawn()
+            .unwrap();
+        send(&events);
+        drop(events);
+        while !handle.is_finished() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        handle.stop().unwrap();
+        let seen = seen.lock().unwrap();
+        seen.clone()
+    }
+
+    fn changed(w: &Path, path: &str, kind: ChangeKind, paths: &[&str]) -> Change {
+        Change {
+            paths: paths.iter().map(|path| w.join(path)).collect(),
+            ..Change::new(w.join(path), kind, w.to_path_buf())
+        }
+    }
+
+    fn write(w: &Path, path: &str) -> Change {
+        changed(w, path, ChangeKind::Write, &[path])
+    }
+
+    #[test]
+    fn changes_to_watched_files_run_the_actions() {
+        let (_dir, w) = tree(&["a.txt", "sub/b.txt"]);
+        let seen = dispatched(WatchEntry::new(&w), |events| {
+            for event in [
+                DebouncedEvent::NoticeWrite(w.join("a.txt")),
+                DebouncedEvent::NoticeWrite(w.join("unknown.txt")),
+                DebouncedEvent::NoticeWrite(PathBuf::from("/elsewhere/c.txt")),
+                DebouncedEvent::Write(w.join("sub/b.txt")),
+                DebouncedEvent::Remove(w.join("a.txt")),
+            ] {
+                events.send(event).unwrap();
+            }
+        });
+        assert_eq!(seen, [write(&w, "a.txt"), write(&w, "sub/b.txt")]);
+    }
+
+    #[test]
+    fn atomic_saves_are_writes_to_the_real_file() {
+        let (_dir, w) = tree(&["a.txt", ".a.txt.swp"]);
+        let seen = dispatched(WatchEntry::new(&w), |events| {
+            for event in [
+                DebouncedEvent::NoticeWrite(w.join(".a.txt.swp")),
+                DebouncedEvent::Rename(w.join("a.txt.tmp"), w.join("a.txt")),
+                DebouncedEvent::Create(w.join("a.txt")),
+            ] {
+                events.send(event).unwrap();
+            }
+        });
+        assert_eq!(seen, [write(&w, "a.txt"), write(&w, "a.txt")]);
+    }
+
+    #[test]
+    fn changes_inside_the_git_directory_do_not_run_the_actions() {
+        let (_dir, w) = tree(&[".git/HEAD", "a.txt"]);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&seen);
+        Stalker::builder()
+            .watch(WatchEntry::new(&w))
+            .action_fn("record", move |change| {
+                recorded.lock().unwrap().push(change.clone());
+                Ok(String::new())
+            })
+            .event_source(ScriptedSource::new([
+                DebouncedEvent::NoticeWrite(w.join(".git/HEAD")),
+                DebouncedEvent::NoticeWrite(w.join("a.txt")),
+            ]))
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+        assert_eq!(*seen.lock().unwrap(), [write(&w, "a.txt")]);
+    }
+
+    #[test]
+    fn entries_that_appear_along_with_their_parents_run_the_actions() {
+        let (_dir, w) = tree(&[]);
+        let created = |path: PathBuf, root: &PathBuf| Change::new(path, ChangeKind::Create, root.clone());
+
+        let file = w.join("a/b/f.txt");
+        let seen = dispatched(WatchEntry::new(&file), |events| {
+            std::fs::create_dir_all(w.join("a/b")).unwrap();
+            std::fs::write(&file, "").unwrap();
+            // Only the outermost new directory was watched for.
+            events.send(DebouncedEvent::Create(w.join("a"))).unwrap();
+        });
+        assert_eq!(seen, [created(file.clone(), &file)]);
+
+        let dir = w.join("c/d");
+        let seen = dispatched(WatchEntry::new(&dir), |events| {
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("f.txt"), "").unwrap();
+            events.send(DebouncedEvent::Create(w.join("c"))).unwrap();
+        });
+        assert_eq!(seen, [created(dir.clone(), &dir), created(dir.join("f.txt"), &dir)]);
+    }
+
+    #[test]
+    fn a_settled_write_runs_again_only_if_the_file_was_written_again() {
+        let (_dir, w) = tree(&["a.txt", "b.txt"]);
+        let (source, events) = ScriptedSource::channel();
+        let (notices, noticed) = std::sync::mpsc::channel();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&seen);
+        let handle = Stalker::builder()
+            .watch(WatchEntry::new(&w))
+            .action_fn("record", move |change| {
+                recorded.lock().unwrap().push(change.clone());
+                Ok(String::new())
+            })
+            .config(InstanceConfig {
+                git_pause: false,
+                ..InstanceConfig::default()
+            })
+            .event_source(source)
+            .on_notice(move |notice| {
+                let _ = notices.send(notice);
+            })
+            .build()
+            .unwrap()
+            .spawn()
+            .unwrap();
+
+        events.send(DebouncedEvent::NoticeWrite(w.join("a.txt"))).unwrap();
+        events.send(DebouncedEvent::NoticeWrite(w.join("b.txt"))).unwrap();
+        // Changes are handled in order, so a.txt is done with by now.
+        while !matches!(noticed.recv().unwrap(), Notice::Changed(change) if change.path == w.join("b.txt")) {}
+        // a.txt is saved again while its first save settles; b.txt isn't.
+        std::fs::write(w.join("a.txt"), "saved again").unwrap();
+        events.send(DebouncedEvent::Write(w.join("a.txt"))).unwrap();
+        events.send(DebouncedEvent::Write(w.join("b.txt"))).unwrap();
+        drop(events);
+        while !handle.is_finished() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        handle.stop().unwrap();
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(*seen, [write(&w, "a.txt"), write(&w, "b.txt"), write(&w, "a.txt")]);
+    }
+
+    #[test]
+    fn a_single_file_is_still_watched_after_a_save_replaces_it() {
+        let (_dir, w) = tree(&["f.txt"]);
+        let file = w.join("f.txt");
+        let (notices, noticed) = std::sync::mpsc::channel();
+        let handle = Stalker::builder()
+            .watch(WatchEntry::new(&file))
+            .action_fn("record", |_| Ok(String::new()))
+            .config(InstanceConfig {
+                git_pause: false,
+                ..InstanceConfig::default()
+            })
+            .debounce(Duration::from_millis(50))
+            .on_notice(move |notice| {
+                let _ = notices.send(notice);
+            })
+            .build()
+            .unwrap()
+            .spawn()
+            .unwrap();
+        let ran_for_file = || {
+            while !matches!(
+                noticed.recv_timeout(Duration::from_secs(10)).unwrap(),
+                Notice::ActionFinished { path, .. } if path == file
+            ) {}
+        };
+
+        std::fs::write(w.join("f.txt.new"), "saved").unwrap();
+        std::fs::rename(w.join("f.txt.new"), &file).unwrap();
+        ran_for_file();
+        std::fs::write(&file, "saved again").unwrap();
+        ran_for_file();
+        handle.stop().unwrap();
+    }
+
+    // Pauses a stalker watching `w` in `mode`, writes to `writes` and
+    // resumes, returning the changes its action ran for (ending with a
+    // trigger of b.txt made while paused) and every notice.
+    fn run_paused(w: &Path, mode: PauseMode, writes: &[&str]) -> (Vec<Change>, Vec<Notice>) {
+        let (source, events) = ScriptedSource::channel();
+        let (notices, noticed) = std::sync::mpsc::channel();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&seen);
+        let stalker = Stalker::builder()
+            .watch(WatchEntry::new(w))
+            .action_fn("record", move |change| {
+                recorded.lock().unwrap().push(change.clone());
+                Ok(String::new())
+            })
+            .event_source(source)
+            .on_notice(move |notice| {
+                let _ = notices.send(notice);
+            })
+            .build()
+            .unwrap();
+        let controller = stalker.controller();
+        let handle = stalker.spawn().unwrap();
+
+        controller.pause_with(mode);
+        let mut received = Vec::new();
+        let mut wait_for = |noticed_it: &dyn Fn(&Notice) -> bool| loop {
+            let notice = noticed.recv().unwrap();
+            let done = noticed_it(&notice);
+            received.push(notice);
+            if done {
+                break;
+            }
+        };
+        for path in writes {
+            let path = w.join(path);
+            events.send(DebouncedEvent::NoticeWrite(path.clone())).unwrap();
+            wait_for(&|notice| matches!(notice, Notice::Changed(change) if change.path == path));
+        }
+        controller.trigger(Some(w.join("b.txt"))).unwrap();
+        controller.resume();
+        wait_for(&|notice| matches!(notice, Notice::Resumed { .. }));
+        // Held changes run right after the resume is noticed, before the
+        // next request is served.
+        controller.trigger(Some(w.join("a.txt"))).unwrap();
+        drop(events);
+        handle.stop().unwrap();
+
+        received.extend(noticed.try_iter());
+        let seen = seen.lock().unwrap();
+        (seen.clone(), received)
+    }
+
+    #[test]
+    fn accumulated_changes_run_on_resume_and_triggers_run_while_paused() {
+        let (_dir, w) = tree(&["a.txt", "b.txt"]);
+        let (seen, _) = run_paused(&w, PauseMode::Accumulate, &["a.txt", "b.txt", "a.txt"]);
+        assert_eq!(
+            seen,
+            [
+                changed(&w, "b.txt", ChangeKind::Trigger, &["b.txt"]),
+                write(&w, "b.txt"),
+                write(&w, "a.txt"),
+                changed(&w, "a.txt", ChangeKind::Trigger, &["a.txt"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn once_runs_the_last_change_with_every_held_path() {
+        let (_dir, w) = tree(&["a.txt", "b.txt"]);
+        let (seen, notices) = run_paused(&w, PauseMode::Once, &["a.txt", "b.txt", "a.txt"]);
+        assert_eq!(
+            seen,
+            [
+                changed(&w, "b.txt", ChangeKind::Trigger, &["b.txt"]),
+                changed(&w, "a.txt", ChangeKind::Write, &["b.txt", "a.txt"]),
+                changed(&w, "a.txt", ChangeKind::Trigger, &["a.txt"]),
+            ]
+        );
+        // Each change is reported once, not again when it runs.
+        let writes = notices
+            .iter()
+            .filter(|notice| matches!(notice, Notice::Changed(change) if change.kind == ChangeKind::Write))
+            .count();
+        assert_eq!(writes, 3);
+    }
+
+    // Watches `outer` and `outer/b` from an instance, drops `removed` from
+    // its stalklist while running, and checks a write under `outer/b` still
+    // runs the actions.
+    fn still_watched_after_dropping(removed: &str) {
+        let dir = tempfile::tempdir().unwrap();
+        let base = std::fs::canonicalize(dir.path()).unwrap();
+        let (instance, outer, inner) = (base.join("instance"), base.join("w"), base.join("w/b"));
+        for dir in [&instance, &inner] {
+            std::fs::create_dir_all(dir).unwrap();
+        }
+        let file = inner.join("f.txt");
+        std::fs::write(&file, "0").unwrap();
+        let stalklist = |entries: &[&Path]| {
+            let lines: String = entries
+                .iter()
+                .map(|entry| format!("{}\n", entry.display()))
+                .collect();
+            std::fs::write(instance.join("stalklist.txt"), lines).unwrap();
+        };
+        stalklist(&[&outer, &inner]);
+        std::fs::write(instance.join("actionlist.txt"), "").unwrap();
+
+        let (notices, noticed) = std::sync::mpsc::channel();
+        let stalker = StalkerBuilder::from_instance(&instance)
+            .unwrap()
+            .config(InstanceConfig {
+                git_pause: false,
+                ..InstanceConfig::default()
+            })
+            .debounce(Duration::from_millis(50))
+            .action_fn("record", |_| Ok(String::new()))
+            .on_notice(move |notice| {
+                let _ = notices.send(notice);
+            })
+            .build()
+            .unwrap();
+        let controller = stalker.controller();
+        let handle = stalker.spawn().unwrap();
+
+        stalklist(&[if removed == "outer" { &inner } else { &outer }]);
+        let timeout = Duration::from_secs(10);
+        while !matches!(noticed.recv_timeout(timeout).unwrap(), Notice::Unwatched(_)) {}
+        // Requests are served between reloads, so the reload is done once
+        // this has run.
+        controller.trigger(Some(inner.clone())).unwrap();
+        std::fs::write(&file, "1").unwrap();
+        while !matches!(
+            noticed.recv_timeout(timeout).unwrap(),
+            Notice::ActionFinished { path, .. } if path == file
+        ) {}
+        handle.stop().unwrap();
+    }
+
+    #[test]
+    fn dropping_an_outer_entry_keeps_watching_a_nested_one() {
+        still_watched_after_dropping("outer");
+    }
+
+    #[test]
+    fn dropping_a_nested_entry_keeps_watching_the_outer_one() {
+        still_watched_after_dropping("inner");
+    }
+}
@@ -0,0 +1,262 @@
+use notify::{watcher, DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use walkdir::WalkDir;
+
+/// What an `EventSource` has to offer next.
+#[derive(Debug)]
+pub enum Next {
+    Event(DebouncedEvent),
+    /// Nothing happened within the timeout.
+    Idle,
+    /// No more events will come; the stalker stops.
+    Finished,
+}
+
+/// Where a running stalker gets its file events from.
+pub trait EventSource: Send {
+    /// Starts reporting changes at `path`, and below it if `mode` is recursive.
+    fn watch(&mut self, path: &Path, mode: RecursiveMode) -> notify::Result<()>;
+
+    /// Stops reporting changes at a path passed to `watch`. Sources that
+    /// can't may keep reporting them; the stalker ignores events for paths
+    /// it no longer watches.
+    fn unwatch(&mut self, path: &Path) -> notify::Result<()> {
+        let _ = path;
+        Ok(())
+    }
+
+    /// Waits up to `timeout` for the next event.
+    fn next(&mut self, timeout: Duration) -> notify::Result<Next>;
+
+    /// How long after a change its event may arrive, e.g. the debounce time.
+    fn delay(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+/// Events from the operating system's file watching API, debounced by notify.
+pub struct NotifySource {
+    watcher: RecommendedWatcher,
+    rx: Receiver<DebouncedEvent>,
+    debounce: Duration,
+}
+
+impl NotifySource {
+    pub fn new(debounce: Duration) -> notify::Result<NotifySource> {
+        let (tx, rx) = channel();
+        Ok(NotifySource {
+            watcher: watcher(tx, debounce)?,
+            rx,
+            debounce,
+        })
+    }
+}
+
+impl EventSource for NotifySource {
+    fn watch(&mut self, path: &Path, mode: RecursiveMode) -> notify::Result<()> {
+        self.watcher.watch(path, mode)
+    }
+
+    fn unwatch(&mut self, path: &Path) -> notify::Result<()> {
+        self.watcher.unwatch(path)
+    }
+
+    fn next(&mut self, timeout: Duration) -> notify::Result<Next> {
+        match self.rx.recv_timeout(timeout) {
+            Ok(event) => Ok(Next::Event(event)),
+            Err(RecvTimeoutError::Timeout) => Ok(Next::Idle),
+            Err(RecvTimeoutError::Disconnected) => Err(notify::Error::Generic(String::from(
+                "the watcher stopped sending events",
+            ))),
+        }
+    }
+
+    fn delay(&self) -> Duration {
+        self.debounce
+    }
+}
+
+/// Events found by comparing modification times every `interval`, for
+/// filesystems the operating system can't watch (e.g. network mounts).
+pub struct PollSource {
+    interval: Duration,
+    roots: Vec<(PathBuf, RecursiveMode)>,
+    modified: HashMap<PathBuf, Option<SystemTime>>,
+    queue: VecDeque<DebouncedEvent>,
+    next_scan: Instant,
+}
+
+impl PollSource {
+    pub fn new(interval: Duration) -> PollSource {
+        PollSource {
+            interval,
+            roots: Vec::new(),
+            modified: HashMap::new(),
+            queue: VecDeque::new(),
+            next_scan: Instant::now() + interval,
+        }
+    }
+
+    fn walk(root: &Path, mode: RecursiveMode) -> impl Iterator<Item = (PathBuf, Option<SystemTime>)> {
+        let mut walker = WalkDir::new(root);
+        if mode == RecursiveMode::NonRecursive {
+            walker = walker.max_depth(1);
+        }
+        walker.into_iter().filter_map(Result::ok).map(|entry| {
+            // A directory's modification time changes whenever something in
+            // it is created or removed, which notify doesn't report as a
+            // write to the directory either.
+            let modified = entry
+                .metadata()
+                .ok()
+                .filter(|m| !m.is_dir())
+                .and_then(|m| m.modified().ok());
+            (entry.into_path(), modified)
+        })
+    }
+
+    fn scan(&mut self) {
+        let mut current = HashMap::new();
+        for (root, mode) in &self.roots {
+            current.extend(PollSource::walk(root, *mode));
+        }
+        for (path, modified) in &current {
+            match self.modified.get(path) {
+                None => self.queue.push_back(DebouncedEvent::Create(path.clone())),
+                Some(before) if before != modified => {
+                    self.queue.push_back(DebouncedEvent::NoticeWrite(path.clone()))
+                }
+                Some(_) => {}
+            }
+        }
+        for path in self.modified.keys().filter(|path| !current.contains_key(*path)) {
+            self.queue.push_back(DebouncedEvent::Remove(path.clone()));
+        }
+        self.modified = current;
+    }
+}
+
+impl EventSource for PollSource {
+    fn watch(&mut self, path: &Path, mode: RecursiveMode) -> notify::Result<()> {
+        fs::symlink_metadata(path)?;
+        // Whatever is there now is the baseline, not a change.
+        self.modified.extend(PollSource::walk(path, mode));
+        self.roots.push((path.to_path_buf(), mode));
+        Ok(())
+    }
+
+    fn unwatch(&mut self, path: &Path) -> notify::Result<()> {
+        self.roots.retain(|(root, _)| root != path);
+        let roots = &self.roots;
+        self.modified.retain(|modified, _| {
+            roots.iter().any(|(root, mode)| match mode {
+                RecursiveMode::Recursive => modified.starts_with(root),
+                RecursiveMode::NonRecursive => modified == root || modified.parent() == Some(root),
+            })
+        });
+        Ok(())
+    }
+
+    fn next(&mut self, timeout: Duration) -> notify::Result<Next> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(event) = self.queue.pop_front() {
+                return Ok(Next::Event(event));
+            }
+            let now = Instant::now();
+            if now >= self.next_scan {
+                self.scan();
+                self.next_scan = now + self.interval;
+            } else if now >= deadline {
+                return Ok(Next::Idle);
+            } else {
+                thread::sleep(self.next_scan.min(deadline) - now);
+            }
+        }
+    }
+
+    fn delay(&self) -> Duration {
+        self.interval
+    }
+}
+
+/// Events handed over in memory instead of read from the filesystem, so the
+/// dispatch of events to actions can be driven deterministically (e.g. in
+/// tests). The stalker still walks the watched paths on disk, so the files
+/// the events are about have to exist there.
+pub struct ScriptedSource {
+    rx: Receiver<DebouncedEvent>,
+}
+
+impl ScriptedSource {
+    /// A source that delivers `events` in order and then finishes.
+    pub fn new(events: impl IntoIterator<Item = DebouncedEvent>) -> ScriptedSource {
+        let (source, tx) = ScriptedSource::channel();
+        for event in events {
+            // The receiver is alive, so sending can't fail.
+            let _ = tx.send(event);
+        }
+        source
+    }
+
+    /// A source fed through the returned sender; it finishes once every
+    /// sender is dropped and the events sent are used up.
+    pub fn channel() -> (ScriptedSource, Sender<DebouncedEvent>) {
+        let (tx, rx) = channel();
+        (ScriptedSource { rx }, tx)
+    }
+}
+
+impl EventSource for ScriptedSource {
+    fn watch(&mut self, _path: &Path, _mode: RecursiveMode) -> notify::Result<()> {
+        Ok(())
+    }
+
+    fn next(&mut self, timeout: Duration) -> notify::Result<Next> {
+        match self.rx.recv_timeout(timeout) {
+            Ok(event) => Ok(Next::Event(event)),
+            Err(RecvTimeoutError::Timeout) => Ok(Next::Idle),
+            Err(RecvTimeoutError::Disconnected) => Ok(Next::Finished),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(source: &mut PollSource) -> Vec<DebouncedEvent> {
+        let mut events = Vec::new();
+        while let Next::Event(event) = source.next(Duration::from_millis(50)).unwrap() {
+            events.push(event);
+        }
+        events
+    }
+
+    #[test]
+    fn polling_reports_files_but_not_the_directories_they_are_in() {
+        let dir = tempfile::tempdir().unwrap();
+        let existing = dir.path().join("a.txt");
+        fs::write(&existing, "1").unwrap();
+        let mut source = PollSource::new(Duration::from_millis(10));
+        source.watch(dir.path(), RecursiveMode::Recursive).unwrap();
+
+        let created = dir.path().join("b.txt");
+        fs::write(&created, "1").unwrap();
+        assert_eq!(events(&mut source), [DebouncedEvent::Create(created.clone())]);
+
+        fs::write(&existing, "22").unwrap();
+        fs::remove_file(&created).unwrap();
+        let mut changed = events(&mut source);
+        changed.sort_by_key(|event| format!("{:?}", event));
+        assert_eq!(
+            changed,
+            [DebouncedEvent::NoticeWrite(existing), DebouncedEvent::Remove(created)]
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use crate::entry::SymlinkReport;
+use std::path::{Path, PathBuf};
+
+struct Link {
+    link: PathBuf,
+    target: PathBuf,
+    report: SymlinkReport,
+}
+
+/// Symlinks found while walking entries with `follow_symlinks`, used to
+/// translate event paths between the link and the resolved target.
+#[derive(Default)]
+pub struct SymlinkMap {
+    links: Vec<Link>,
+}
+
+impl SymlinkMap {
+    pub fn insert(&mut self, link: PathBuf, target: PathBuf, report: SymlinkReport) {
+        self.links.push(Link {
+            link,
+            target,
+            report,
+        });
+    }
+
+    pub fn targets(&self) -> impl Iterator<Item = &Path> {
+        self.links.iter().map(|l| l.target.as_path())
+    }
+
+    /// Rewrites a path under a resolved target into the same path under its link.
+    pub fn to_link(&self, path: &Path) -> Option<PathBuf> {
+        self.links
+            .iter()
+            .filter_map(|l| path.strip_prefix(&l.target).ok().map(|rest| (l, rest)))
+            .max_by_key(|(l, _)| l.target.components().count())
+            .map(|(l, rest)| join(&l.link, rest))
+    }
+
+    /// The path an event on `path` (in link form) should be reported as.
+    pub fn reported(&self, path: &Path) -> PathBuf {
+        let innermost = self
+            .links
+            .iter()
+            .filter_map(|l| path.strip_prefix(&l.link).ok().map(|rest| (l, rest)))
+            .max_by_key(|(l, _)| l.link.components().count());
+
+        match innermost {
+            Some((l, rest)) if l.report == SymlinkReport::Target => join(&l.target, rest),
+            _ => path.to_path_buf(),
+        }
+    }
+}
+
+// Path::join with an empty path appends a trailing separator, which would no
+// longer compare equal to the walked path.
+fn join(base: &Path, rest: &Path) -> PathBuf {
+    if rest.as_os_str().is_empty() {
+        base.to_path_buf()
+    } else {
+        base.join(rest)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::entry::{SymlinkReport, WatchEntry};
+    use crate::filter::EditorFilter;
+    use crate::source::ScriptedSource;
+    use crate::watch::{Added, WatchSet};
+    use std::fs;
+    use std::os::unix::fs::symlink;
+    use std::path::{Path, PathBuf};
+    use tempfile::TempDir;
+
+    // A project with `vendor` linked to a directory outside of it.
+    fn project() -> (TempDir, PathBuf, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let base = fs::canonicalize(dir.path()).unwrap();
+        let project = base.join("project");
+        let vendor = base.join("opt/vendor");
+        fs::create_dir_all(&project).unwrap();
+        fs::create_dir_all(&vendor).unwrap();
+        fs::write(vendor.join("lib.rs"), "").unwrap();
+        symlink(&vendor, project.join("vendor")).unwrap();
+        (dir, project, vendor)
+    }
+
+    fn entry(path: &Path, follow_symlinks: bool, report: SymlinkReport) -> WatchEntry {
+        WatchEntry {
+            follow_symlinks,
+            report,
+            ..WatchEntry::new(path)
+        }
+    }
+
+    fn add(watch_set: &mut WatchSet, entry: &WatchEntry) -> Vec<walkdir::Error> {
+        let filter = EditorFilter::new(true, &[]).unwrap();
+        match watch_set.add(entry, &mut ScriptedSource::new([]), &filter).unwrap() {
+            Added::Active(errors) => errors,
+            Added::Pending(_) => panic!("{} should exist", entry.path.display()),
+        }
+    }
+
+    #[test]
+    fn only_entries_that_follow_symlinks_see_through_them() {
+        let (_dir, project, vendor) = project();
+        let lib = vendor.join("lib.rs");
+
+        let mut watch_set = WatchSet::default();
+        add(&mut watch_set, &entry(&project, false, SymlinkReport::Link));
+        assert_eq!(watch_set.resolve(lib.clone()), None);
+        assert!(watch_set.roots().all(|(root, _)| root != vendor));
+
+        let mut watch_set = WatchSet::default();
+        add(&mut watch_set, &entry(&project, true, SymlinkReport::Link));
+        let through_link = project.join("vendor/lib.rs");
+        assert_eq!(watch_set.resolve(lib), Some((through_link.clone(), project.clone())));
+        assert_eq!(watch_set.reported(&through_link), through_link);
+        assert!(watch_set.roots().any(|(root, _)| root == vendor));
+    }
+
+    #[test]
+    fn links_can_be_reported_as_their_targets() {
+        let (_dir, project, vendor) = project();
+        let mut watch_set = WatchSet::default();
+        add(&mut watch_set, &entry(&project, true, SymlinkReport::Target));
+
+        let (path, root) = watch_set.resolve(vendor.join("lib.rs")).unwrap();
+        assert_eq!(path, project.join("vendor/lib.rs"));
+        assert_eq!(root, project);
+        assert_eq!(watch_set.reported(&path), vendor.join("lib.rs"));
+        assert_eq!(watch_set.reported(&project.join("vendor")), vendor);
+        // Paths outside of the link are reported as they are.
+        assert_eq!(watch_set.reported(&project), project);
+    }
+
+    #[test]
+    fn symlink_cycles_are_skipped() {
+        let (_dir, project, _vendor) = project();
+        symlink(&project, project.join("again")).unwrap();
+        let mut watch_set = WatchSet::default();
+
+        let errors = add(&mut watch_set, &entry(&project, true, SymlinkReport::Link));
+        let cycles: Vec<&Path> = errors.iter().filter_map(|e| e.loop_ancestor()).collect();
+        assert_eq!(cycles, [project.as_path()]);
+        // The link closing the cycle is left out, and the rest is walked once.
+        assert_eq!(
+            watch_set.walked(&project),
+            [project.clone(), project.join("vendor"), project.join("vendor/lib.rs")]
+        );
+    }
+}
@@ -0,0 +1,239 @@
+use crate::entry::WatchEntry;
+use crate::filter::EditorFilter;
+use crate::source::EventSource;
+use crate::symlink::SymlinkMap;
+use notify::RecursiveMode;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// The outcome of adding an entry to a `WatchSet`.
+pub enum Added {
+    /// The entry is being watched. Carries the errors hit while walking it,
+    /// which only cost the paths they occurred on.
+    Active(Vec<walkdir::Error>),
+    /// The path doesn't exist yet; its nearest existing ancestor is watched
+    /// until it appears.
+    Pending(PathBuf),
+}
+
+/// Every path the running stalker dispatches actions for, and the
+/// bookkeeping needed to keep the event source in sync with it.
+#[derive(Default)]
+pub struct WatchSet {
+    /// Walked paths, each with the stalklist entry it was found under.
+    paths: HashMap<PathBuf, PathBuf>,
+    roots: Vec<(PathBuf, RecursiveMode)>,
+    symlinks: SymlinkMap,
+    pending: Vec<WatchEntry>,
+}
+
+impl WatchSet {
+    pub fn add<S: EventSource + ?Sized>(
+        &mut self,
+        entry: &WatchEntry,
+        source: &mut S,
+        filter: &EditorFilter,
+    ) -> notify::Result<Added> {
+        if fs::symlink_metadata(&entry.path).is_err() {
+            let ancestor = self.watch_nearest_ancestor(&entry.path, source)?;
+            // The path may have shown up while the ancestor watch was being set up.
+            if fs::symlink_metadata(&entry.path).is_err() {
+                self.pending.push(entry.clone());
+                return Ok(Added::Pending(ancestor));
+            }
+        }
+
+        let mut errors = Vec::new();
+        let mut roots = Vec::new();
+        let is_dir = fs::metadata(&entry.path).is_ok_and(|metadata| metadata.is_dir());
+        if !is_dir {
+            // A watch on the file itself ends with the first save that
+            // replaces it, so its directory is watched instead.
+            roots.push((parent_dir(&entry.path), RecursiveMode::NonRecursive));
+        } else if !entry.watches_per_directory() {
+            roots.push((entry.path.clone(), RecursiveMode::Recursive));
+        }
+
+        let mut walker = WalkDir::new(&entry.path).follow_links(entry.follow_symlinks);
+        if let Some(depth) = entry.max_depth {
+            walker = walker.max_depth(depth);
+        }
+        let walker = walker.into_iter().filter_entry(|e| {
+            e.depth() == 0
+                || !((entry.no_hidden && is_hidden(e.file_name())) || filter.is_ignored(e.path()))
+        });
+        for item in walker {
+            match item {
+                Ok(true_path) => {
+                    // Directories at the depth limit are listed but their contents aren't.
+                    if is_dir
+                        && entry.watches_per_directory()
+                        && (true_path.depth() == 0
+                            || (true_path.file_type().is_dir()
+                                && entry
+                                    .max_depth
+                                    .is_none_or(|depth| true_path.depth() < depth)))
+                    {
+                        roots.push((true_path.path().to_owned(), RecursiveMode::NonRecursive));
+                    }
+                    if entry.follow_symlinks && true_path.path_is_symlink() {
+                        if let Ok(target) = fs::canonicalize(true_path.path()) {
+                            self.symlinks
+                                .insert(true_path.path().to_owned(), target, entry.report);
+                        }
+                    }
+                    self.paths
+                        .insert(true_path.path().to_owned(), entry.path.clone());
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+
+        for (root, mode) in roots {
+            source.watch(&root, mode)?;
+            self.roots.push((root, mode));
+        }
+        // Not every backend follows symlinks on its own, so watch the resolved
+        // targets that aren't already covered by a root.
+        let uncovered: Vec<PathBuf> = self
+            .symlinks
+            .targets()
+            .filter(|target| !self.is_covered(target))
+            .map(Path::to_path_buf)
+            .collect();
+        for target in uncovered {
+            source.watch(&target, RecursiveMode::Recursive)?;
+            self.roots.push((target, RecursiveMode::Recursive));
+        }
+
+        Ok(Added::Active(errors))
+    }
+
+    /// Called when `created` appears on disk. Re-adds every pending entry at
+    /// or below it, returning each entry with its new state.
+    pub fn created<S: EventSource + ?Sized>(
+        &mut self,
+        created: &Path,
+        source: &mut S,
+        filter: &EditorFilter,
+    ) -> Vec<(WatchEntry, notify::Result<Added>)> {
+        let (ready, waiting) = self
+            .pending
+            .drain(..)
+            .partition(|entry| entry.path.starts_with(created));
+        self.pending = waiting;
+
+        ready
+            .into_iter()
+            .map(|entry: WatchEntry| {
+                let added = self.add(&entry, source, filter);
+                (entry, added)
+            })
+            .collect()
+    }
+
+    /// Watches a single file that isn't part of the stalklist (such as the
+    /// env_file). Its directory is watched so that atomic saves, which
+    /// replace the file, are still seen.
+    pub fn watch_file<S: EventSource + ?Sized>(&mut self, path: &Path, source: &mut S) -> notify::Result<()> {
+        let dir = parent_dir(path);
+        if !self.is_covered(&dir) && !self.roots.iter().any(|(root, _)| *root == dir) {
+            source.watch(&dir, RecursiveMode::NonRecursive)?;
+            self.roots.push((dir, RecursiveMode::NonRecursive));
+        }
+        Ok(())
+    }
+
+    /// Maps an event path to the walked path it belongs to, if any, along
+    /// with the root of its stalklist entry.
+    pub fn resolve(&self, changed: PathBuf) -> Option<(PathBuf, PathBuf)> {
+        let path = if self.paths.contains_key(&changed) {
+            changed
+        } else {
+            self.symlinks.to_link(&changed)?
+        };
+        let root = self.paths.get(&path)?.clone();
+        Some((path, root))
+    }
+
+    /// The walked paths of the stalklist entry at `root`, in order.
+    pub fn walked(&self, root: &Path) -> Vec<PathBuf> {
+        let mut walked: Vec<PathBuf> = self
+            .paths
+            .iter()
+            .filter(|(_, entry)| *entry == root)
+            .map(|(path, _)| path.clone())
+            .collect();
+        walked.sort();
+        walked
+    }
+
+    /// Every walked path.
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.paths.keys().map(PathBuf::as_path)
+    }
+
+    /// Every path handed to the event source to watch, and how.
+    pub fn roots(&self) -> impl Iterator<Item = (&Path, RecursiveMode)> {
+        self.roots.iter().map(|(root, mode)| (root.as_path(), *mode))
+    }
+
+    /// The path an event on a resolved path is reported (and substituted) as.
+    pub fn reported(&self, path: &Path) -> PathBuf {
+        self.symlinks.reported(path)
+    }
+
+    fn is_covered(&self, path: &Path) -> bool {
+        self.roots
+            .iter()
+            .any(|(root, mode)| *mode == RecursiveMode::Recursive && path.starts_with(root))
+    }
+
+    fn watch_nearest_ancestor<S: EventSource + ?Sized>(
+        &mut self,
+        path: &Path,
+        source: &mut S,
+    ) -> notify::Result<PathBuf> {
+        loop {
+            let ancestor = nearest_existing_ancestor(path);
+            if !self.is_covered(&ancestor) {
+                source.watch(&ancestor, RecursiveMode::NonRecursive)?;
+                self.roots
+                    .push((ancestor.clone(), RecursiveMode::NonRecursive));
+            }
+            // A directory closer to the path may have been created in the meantime.
+            if nearest_existing_ancestor(path) == ancestor {
+                return Ok(ancestor);
+            }
+        }
+    }
+}
+
+fn parent_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+fn nearest_existing_ancestor(path: &Path) -> PathBuf {
+    path.ancestors()
+        .skip(1)
+        .map(|a| {
+            if a.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                a
+            }
+        })
+        .find(|a| a.exists())
+        .unwrap_or_else(|| Path::new("/"))
+        .to_path_buf()
+}
+
+fn is_hidden(name: &OsStr) -> bool {
+    name.to_str().is_some_and(|name| name.starts_with('.'))
+}